@@ -1,12 +1,39 @@
+pub mod depth_table;
+mod ancestors;
+mod error;
+pub mod fill;
+pub mod jump;
+#[cfg(feature = "parallel")]
+mod parallel;
+pub mod parity;
+pub mod rules;
+pub mod sieve;
+pub mod stream;
+pub mod value;
 pub mod viz;
 
-use std::collections::{HashMap, VecDeque};
-use std::marker::PhantomData;
-use std::ops::Range;
-use std::ptr::NonNull;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::mem::size_of;
+use std::ops::{Range, RangeInclusive};
 
+use itertools::Either;
 use wasm_bindgen::prelude::wasm_bindgen;
 
+use ancestors::AncestorIndex;
+use rules::DownRule;
+
+pub use depth_table::{DepthOrbit, DepthTable};
+pub use error::CollatzError;
+pub use fill::FillProgress;
+pub use jump::JumpTable;
+pub use parity::{terras_class, ParityVector, TerrasClass};
+pub use rules::{derive_rules, Rules};
+pub use sieve::{Sieve, SieveReport};
+pub use stream::{StepStats, StreamOrbit};
+pub use value::Value;
+
 #[wasm_bindgen]
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum CollatzKind {
@@ -14,6 +41,7 @@ pub enum CollatzKind {
     Short = 1,
     Odd = 2,
     Compact = 3,
+    Affine = 4,
 }
 
 impl From<i32> for CollatzKind {
@@ -23,467 +51,1412 @@ impl From<i32> for CollatzKind {
             1 => Self::Short,
             2 => Self::Odd,
             3 => Self::Compact,
+            4 => Self::Affine,
             _ => Self::Full,
         }
     }
 }
 
-type Node = NonNull<CollatzNode>;
+/// Parameters of the `CollatzKind::Affine` map
+///
+/// `n` is sent to `n / divisor` when `divisor` divides `n`, and to `multiplier * n + addend`
+/// otherwise. With `shortcut`, the second case is also divided by `divisor`, as `Short` does
+/// for `Full`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AffineMap {
+    pub multiplier: u64,
+    pub addend: u64,
+    pub divisor: u64,
+    pub shortcut: bool,
+}
 
-#[derive(Debug, PartialEq)]
-struct NodeData {
-    value: u64,
+impl Default for AffineMap {
+    fn default() -> Self {
+        Self {
+            multiplier: 3,
+            addend: 1,
+            divisor: 2,
+            shortcut: false,
+        }
+    }
+}
+
+impl AffineMap {
+    pub fn new(
+        multiplier: u64,
+        addend: u64,
+        divisor: u64,
+        shortcut: bool,
+    ) -> Result<Self, CollatzError> {
+        let map = Self {
+            multiplier,
+            addend,
+            divisor,
+            shortcut,
+        };
+        if multiplier == 0 || divisor < 2 {
+            return Err(CollatzError::InvalidMap(map));
+        }
+        // The shortcut division has to be exact for every `n` that is not divisible
+        if shortcut
+            && (1..divisor).any(|r| !(multiplier % divisor * r + addend % divisor).is_multiple_of(divisor))
+        {
+            return Err(CollatzError::InvalidMap(map));
+        }
+        Ok(map)
+    }
+}
+
+impl std::fmt::Display for AffineMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}n+{}", self.multiplier, self.addend)?;
+        if self.shortcut {
+            write!(f, " (shortcut)")?;
+        }
+        if self.divisor != 2 {
+            write!(f, " with divisor {}", self.divisor)?;
+        }
+        Ok(())
+    }
+}
+
+/// Index of a node in `Collatz::arena`
+type Node = u32;
+
+/// Depth of nodes created by a `generate_down` that has not yet found where the orbit ends
+const PENDING: usize = usize::MAX;
+
+/// Most values [`Collatz::inverse_layer_counts`] keeps in a layer before it stops
+const MAX_LAYER_WIDTH: usize = 1 << 20;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeData<T = u64> {
+    pub value: T,
     // Orbit stats
-    depth: usize,
-    highest_point: u64,
+    pub depth: usize,
+    /// The value of the orbit furthest from zero
+    pub highest_point: T,
+    /// Number of steps until the orbit first gets closer to zero than `value`, or `None` if
+    /// it never does
+    pub stopping_time: Option<usize>,
+    /// Number of steps that multiply, rather than only divide
+    pub odd_steps: usize,
+    /// Number of steps until the orbit reaches `highest_point`
+    pub peak_step: usize,
 }
-impl From<(usize, u64, u64)> for NodeData {
-    fn from(value: (usize, u64, u64)) -> Self {
+
+impl<T: Clone> NodeData<T> {
+    /// The stats of a root, whose orbit ends right away.
+    fn new(value: T) -> Self {
         Self {
-            value: value.1,
-            depth: value.0,
-            highest_point: value.2,
+            highest_point: value.clone(),
+            value,
+            depth: 0,
+            stopping_time: None,
+            odd_steps: 0,
+            peak_step: 0,
         }
     }
+
+    /// Same as `stopping_time`, under the name Roosendaal uses in his records.
+    pub fn glide(&self) -> Option<usize> {
+        self.stopping_time
+    }
 }
 
-struct CollatzNode {
-    data: NodeData,
-    // Node pointers
+/// Where the orbits of several values meet, see [`Collatz::find_merge`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Merge<T = u64> {
+    /// The first value the orbits have in common
+    pub value: T,
+    /// Number of steps from each value to `value`, in the order the values were given
+    pub steps: Vec<usize>,
+    /// Number of edges of the tree joining the values to `value`, which for two values is
+    /// the distance between them in the tree
+    pub distance: usize,
+}
+
+#[derive(Clone)]
+struct CollatzNode<T> {
+    data: NodeData<T>,
+    // Node indices
     down: Option<Node>,
     up1: Option<Node>,
     up2: Option<Node>,
 }
 
-impl CollatzNode {
-    pub fn new(value: u64) -> Self {
+impl<T: Value> CollatzNode<T> {
+    pub fn new(value: T) -> Self {
         CollatzNode {
-            data: NodeData {
-                value,
-                depth: 0,
-                highest_point: value,
-            },
+            data: NodeData::new(value),
             down: None,
             up1: None,
             up2: None,
         }
     }
+
+    /// Links `child` as a predecessor of this node.
+    fn link_up(&mut self, child: Node) {
+        if self.up1.is_none() {
+            self.up1 = Some(child);
+        } else {
+            self.up2 = Some(child);
+        }
+    }
+
+    /// Removes `child` from the predecessors of this node, keeping `up1` filled first.
+    fn unlink_up(&mut self, child: Node) {
+        if self.up1 == Some(child) {
+            self.up1 = self.up2.take();
+        } else if self.up2 == Some(child) {
+            self.up2 = None;
+        }
+    }
 }
 
-impl std::fmt::Display for CollatzNode {
+impl<T: Value> std::fmt::Display for CollatzNode<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "({}, {})", self.data.depth, self.data.value)
     }
 }
 
-pub struct Collatz {
+#[derive(Clone)]
+pub struct Collatz<T = u64> {
     kind: CollatzKind,
-    root: Node,
-    nodes: HashMap<u64, Node>,
-    ranges: Vec<Range<u64>>,
-    _boo: PhantomData<CollatzNode>,
+    map: AffineMap,
+    // Steps of `Odd` and `Compact`, derived from their residue classes
+    rules: Option<Rules>,
+    // One tree per cycle, rooted at its value closest to zero
+    roots: Vec<Node>,
+    cycles: Vec<Vec<T>>,
+    // Every node, linked to each other by index
+    arena: Vec<CollatzNode<T>>,
+    nodes: HashMap<T, Node>,
+    ranges: Vec<Range<T>>,
+    memory_budget: Option<usize>,
+    jump_table: Option<JumpTable>,
+    // Every value in the domain from 1 up to here reaches a cycle of the tree
+    verified_end: T,
+    // Dropped whenever a node is added
+    ancestors: Option<AncestorIndex>,
+    depth_index: Option<Vec<Vec<Node>>>,
+    // Every predecessor reached through values at most this far from zero is in the tree
+    up_filled: Option<T>,
 }
 
 impl Default for Collatz {
     fn default() -> Self {
-        let head = unsafe { NonNull::new_unchecked(Box::into_raw(Box::new(CollatzNode::new(1)))) };
-        let nodes = HashMap::from([(1, head)]);
-        Self {
-            kind: CollatzKind::Full,
-            root: head,
-            nodes,
-            ranges: vec![Range { start: 1, end: 2 }],
-            _boo: PhantomData,
-        }
+        Self::new(CollatzKind::Full)
     }
 }
 
 impl Collatz {
     pub fn new(kind: CollatzKind) -> Self {
-        let head = unsafe { NonNull::new_unchecked(Box::into_raw(Box::new(CollatzNode::new(1)))) };
-        let nodes = HashMap::from([(1, head)]);
-        Self {
+        Self::with_kind(kind)
+    }
+}
+
+impl<T: Value> Collatz<T> {
+    /// Same as [`Collatz::new`], for any [`Value`] type.
+    pub fn with_kind(kind: CollatzKind) -> Self {
+        Self::with_kind_and_map(kind, AffineMap::default())
+    }
+
+    /// A tree of `CollatzKind::Affine` using `map`.
+    pub fn with_map(map: AffineMap) -> Self {
+        Self::with_kind_and_map(CollatzKind::Affine, map)
+    }
+
+    fn with_kind_and_map(kind: CollatzKind, map: AffineMap) -> Self {
+        let one = T::from_u64(1);
+        let rules = match kind {
+            CollatzKind::Odd => Some(derive_rules(3, 0)),
+            CollatzKind::Compact => Some(derive_rules(5, 1)),
+            _ => None,
+        };
+        let mut collatz = Self {
             kind,
-            root: head,
-            nodes,
-            ranges: vec![Range { start: 1, end: 2 }],
-            _boo: PhantomData,
+            map,
+            rules: rules.map(|rules| rules.expect("the rules of the kind derive")),
+            roots: Vec::new(),
+            cycles: Vec::new(),
+            arena: Vec::new(),
+            nodes: HashMap::new(),
+            ranges: Vec::new(),
+            memory_budget: None,
+            jump_table: None,
+            verified_end: one.clone(),
+            ancestors: None,
+            depth_index: None,
+            up_filled: None,
+        };
+        if kind != CollatzKind::Affine {
+            // Every other kind is known to cycle through 1
+            let head = collatz.push_node(CollatzNode::new(one.clone()));
+            let mut cycle = vec![one.clone()];
+            let mut n = collatz.down(one.clone());
+            while n != one {
+                cycle.push(n.clone());
+                n = collatz.down(n);
+            }
+            collatz.roots.push(head);
+            collatz.cycles.push(cycle);
+            collatz.ranges.push(one.clone()..T::from_u64(2));
         }
+        collatz
     }
 
     pub fn kind(&self) -> CollatzKind {
         self.kind
     }
 
-    pub fn contains(&self, n: &u64) -> bool {
-        // self.ranges.iter().any(|r| r.contains(n))
-        if self.ranges[0].contains(n) {
-            true
-        } else {
-            self.nodes.contains_key(n)
+    pub fn map(&self) -> AffineMap {
+        self.map
+    }
+
+    /// The cycles found so far, each starting at its value closest to zero.
+    ///
+    /// Every cycle is the root of its own tree, in the same order.
+    pub fn cycles(&self) -> &[Vec<T>] {
+        &self.cycles
+    }
+
+    /// Index in [`Collatz::cycles`] of the cycle the orbit of `n` ends in, or `None` if `n`
+    /// has not been generated.
+    pub fn cycle_of(&self, n: &T) -> Option<usize> {
+        let mut node = self.get_node(n)?;
+        while let Some(down) = self.node(node).down {
+            node = down;
+        }
+        self.roots.iter().position(|&root| root == node)
+    }
+
+    /// Whether `n` is a value the map of this kind is defined on.
+    ///
+    /// `Odd` only acts on odd numbers and `Compact` on numbers coprime to 6.
+    pub fn in_domain(&self, n: &T) -> bool {
+        match self.kind {
+            CollatzKind::Odd => n.rem_u64(2) != 0,
+            CollatzKind::Compact => n.rem_u64(2) != 0 && n.rem_u64(3) != 0,
+            _ => true,
         }
     }
 
+    pub fn contains(&self, n: &T) -> bool {
+        self.ranges.iter().any(|r| r.contains(n)) || self.nodes.contains_key(n)
+    }
+
+    /// The ranges of start values filled so far, sorted and apart from each other.
+    pub fn ranges(&self) -> &[Range<T>] {
+        &self.ranges
+    }
+
+    /// Whether every start value in `window` has been filled.
+    pub fn covers(&self, window: &Range<T>) -> bool {
+        window.start >= window.end
+            || self
+                .ranges
+                .iter()
+                .any(|r| r.start <= window.start && window.end <= r.end)
+    }
+
+    /// Adds `range` to the filled ranges, merging it with those it overlaps or touches.
+    fn add_range(&mut self, range: Range<T>) {
+        if range.start >= range.end {
+            return;
+        }
+        let mut merged = range;
+        self.ranges.retain(|r| {
+            if r.end < merged.start || merged.end < r.start {
+                return true;
+            }
+            merged.start = merged.start.clone().min(r.start.clone());
+            merged.end = merged.end.clone().max(r.end.clone());
+            false
+        });
+        let i = self.ranges.partition_point(|r| r.start < merged.start);
+        self.ranges.insert(i, merged);
+    }
+
     pub fn len(&self) -> usize {
         self.nodes.len()
     }
 
-    fn get_node(&self, n: u64) -> Option<Node> {
-        self.nodes.get(&n).copied()
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
     }
 
-    pub fn down(&self, n: u64) -> u64 {
-        match &self.kind {
-            CollatzKind::Full => match n % 2 {
-                0 => n / 2,
-                1 => n * 3 + 1,
+    /// Approximate number of bytes each node takes up.
+    pub fn node_size() -> usize {
+        // An arena slot, plus a hash map entry and its control byte
+        size_of::<CollatzNode<T>>() + size_of::<(T, Node)>() + 1
+    }
+
+    /// Approximate number of bytes allocated for the tree.
+    ///
+    /// Heap memory owned by the values themselves, as for big integers, is not counted.
+    pub fn memory_usage(&self) -> usize {
+        self.arena.capacity() * size_of::<CollatzNode<T>>()
+            + self.nodes.capacity() * (size_of::<(T, Node)>() + 1)
+            + self.ancestors.as_ref().map_or(0, AncestorIndex::memory_usage)
+            + self.depth_index.as_ref().map_or(0, |layers| {
+                layers.iter().map(Vec::capacity).sum::<usize>() * size_of::<Node>()
+            })
+    }
+
+    pub fn memory_budget(&self) -> Option<usize> {
+        self.memory_budget
+    }
+
+    /// Limits the tree to `budget` bytes, counting [`Collatz::node_size`] bytes per node.
+    ///
+    /// Generating past the budget fails with `CollatzError::MemoryBudget`. A tree that is
+    /// already over budget is left as is; see [`Collatz::prune`].
+    pub fn set_memory_budget(&mut self, budget: Option<usize>) {
+        self.memory_budget = budget;
+    }
+
+    fn check_memory_budget(&self) -> Result<(), CollatzError<T>> {
+        match self.memory_budget {
+            Some(budget) if (self.len() + 1) * Self::node_size() > budget => {
+                Err(CollatzError::MemoryBudget { budget })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn jump_table(&self) -> Option<&JumpTable> {
+        self.jump_table.as_ref()
+    }
+
+    /// Makes [`Collatz::generate_down`] look for the tree `k` steps at a time with a
+    /// [`JumpTable`], or one step at a time with `None`.
+    ///
+    /// Only the steps of the jump that lands in the tree are checked one at a time, to find
+    /// the merge point. The nodes are still created one step at a time, so the tree is the
+    /// same either way. Fails with `CollatzError::InvalidJumpTable` if `k` is 0 or the table
+    /// would have more than 2^24 entries, leaving the jump table as it was.
+    pub fn set_jump_table(&mut self, k: Option<u32>) -> Result<(), CollatzError<T>> {
+        self.jump_table = match k {
+            Some(k) => Some(
+                JumpTable::new(self.kind, self.map, k)
+                    .ok_or(CollatzError::InvalidJumpTable { k })?,
+            ),
+            None => None,
+        };
+        Ok(())
+    }
+
+    fn get_node(&self, n: &T) -> Option<Node> {
+        self.nodes.get(n).copied()
+    }
+
+    fn node(&self, node: Node) -> &CollatzNode<T> {
+        &self.arena[node as usize]
+    }
+
+    fn node_mut(&mut self, node: Node) -> &mut CollatzNode<T> {
+        &mut self.arena[node as usize]
+    }
+
+    /// Adds `node` to the arena and indexes it by value.
+    fn push_node(&mut self, node: CollatzNode<T>) -> Node {
+        self.ancestors = None;
+        self.depth_index = None;
+        let index = Node::try_from(self.arena.len()).expect("too many nodes for the arena");
+        self.nodes.insert(node.data.value.clone(), index);
+        self.arena.push(node);
+        index
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the next value overflows `T`; see [`Collatz::checked_down`].
+    pub fn down(&self, n: T) -> T {
+        self.checked_down(&n).expect("orbit value overflowed")
+    }
+
+    /// Next value in the orbit of `n`, or `None` if it overflows `T`.
+    pub fn checked_down(&self, n: &T) -> Option<T> {
+        Some(match &self.kind {
+            CollatzKind::Full => match n.rem_u64(2) {
+                0 => n.div_u64(2),
+                1 => n.mul_add(3, 1)?,
                 _ => unreachable!(),
             },
-            CollatzKind::Short => match n % 2 {
-                0 => n / 2,
-                1 => (n * 3 + 1) / 2,
+            CollatzKind::Short => match n.rem_u64(2) {
+                0 => n.div_u64(2),
+                1 => n.mul_add(3, 1)?.div_u64(2),
                 _ => unreachable!(),
             },
-            CollatzKind::Odd => {
-                match n % 8 {
-                    5 => n / 4, // integer arithmetic for (n - 1) / 4
-                    3 | 7 => (3 * n + 1) / 2,
-                    1 => (3 * n + 1) / 4,
-                    _ => unreachable!(),
+            CollatzKind::Odd | CollatzKind::Compact => {
+                match self.rules.as_ref().unwrap().down_rule(n) {
+                    // integer arithmetic for (n - (divisor - 1) / 3) / divisor
+                    Some(DownRule::Shortcut { divisor }) => n.div_u64(divisor),
+                    Some(DownRule::Syracuse { divisor }) => n.mul_add(3, 1)?.div_u64(divisor),
+                    None => unreachable!(),
                 }
             }
-            CollatzKind::Compact => {
-                // match n % 12 {
-                //     7 | 11 => (3 * n + 1) / 2,
-                //     n => match n % 24 {
-                //         5 => n / 4,                    // (n - 1) / 4
-                //         1 | 17 => (3 * n + 1) / 4,
-                //         n => match n % 96 {
-                //             85 => n / 16,              // (n - 5) / 16
-                //             13 | 61 => (3 * n + 1) / 8,
-                //             37 => (3 * n + 1) / 16,
-                //             _ => unreachable!(),
-                //         }
-                //     }
-                // }
-
-                match n % 96 {
-                    5 | 29 | 53 | 77 => n / 4, // (n - 1) / 4
-                    85 => n / 16,              // (n - 5) / 16
-                    7 | 11 | 19 | 23 | 31 | 35 | 43 | 47 | 55 | 59 | 67 | 71 | 79 | 83 | 91
-                    | 95 => (3 * n + 1) / 2,
-                    1 | 17 | 25 | 41 | 49 | 65 | 73 | 89 => (3 * n + 1) / 4,
-                    13 | 61 => (3 * n + 1) / 8,
-                    37 => (3 * n + 1) / 16,
-                    _ => unreachable!(),
+            CollatzKind::Affine => {
+                let AffineMap {
+                    multiplier,
+                    addend,
+                    divisor,
+                    shortcut,
+                } = self.map;
+                match n.rem_u64(divisor) {
+                    0 => n.div_u64(divisor),
+                    _ if shortcut => n.mul_add(multiplier, addend)?.div_u64(divisor),
+                    _ => n.mul_add(multiplier, addend)?,
                 }
             }
-        }
+        })
     }
 
-    pub fn up(&self, n: u64) -> (u64, Option<u64>) {
+    /// # Panics
+    ///
+    /// Panics if the first predecessor overflows `T`; see [`Collatz::checked_up`].
+    pub fn up(&self, n: T) -> (T, Option<T>) {
+        let (up1, up2) = self.checked_up(&n);
+        (up1.expect("orbit value overflowed"), up2)
+    }
+
+    /// Predecessors of `n`, where a predecessor that overflows `T` is `None`.
+    pub fn checked_up(&self, n: &T) -> (Option<T>, Option<T>) {
         match &self.kind {
             CollatzKind::Full => {
                 // n = 3 * (2m + 1) + 1 = 6m + 4        =>  n % 6 == 4
-                match n % 6 {
-                    4 => (n * 2, Some(n / 3)), // (n - 1) / 3
-                    _ => (n * 2, None),
+                match n.rem_u64(6) {
+                    4 => (n.mul_add(2, 0), Some(n.div_u64(3))), // (n - 1) / 3
+                    _ => (n.mul_add(2, 0), None),
                 }
             }
             CollatzKind::Short => {
                 // n = (3(2m + 1) + 1) / 2 = 3m + 2     =>  n % 3 == 2
-                match n % 3 {
-                    2 => (n * 2, Some(n * 2 / 3)),
-                    _ => (n * 2, None),
+                match n.rem_u64(3) {
+                    2 => (n.mul_add(2, 0), mul_div3(n, 2)),
+                    _ => (n.mul_add(2, 0), None),
                 }
             }
-            CollatzKind::Odd => {
-                // n = ((8m+5) - 1) / 4 = 2m + 1        =>  n % 2 == 1
-                // n = (3(8m + 3) + 1) / 2 = 12m + 5    =>  n % 12 == 5
-                // n = (3(8m + 7) + 1) / 2 = 12m + 11   =>  n % 12 == 11
-                // n = (3(8m + 1) + 1) / 4 = 6m + 1     =>  n % 6 == 1
-                match n % 12 {
-                    1 | 7 => (n * 4 + 1, Some(n * 4 / 3)),  // (n * 4 - 1) / 3
-                    5 | 11 => (n * 4 + 1, Some(n * 2 / 3)), // (n * 2 - 1) / 3
-                    3 | 9 => (n * 4 + 1, None),
-                    _ => unreachable!(),
-                }
+            CollatzKind::Odd | CollatzKind::Compact => {
+                let rules = self.rules.as_ref().unwrap();
+                assert!(rules.in_domain(n), "value outside the domain");
+                rules.up(n)
+            }
+            CollatzKind::Affine => {
+                // n = (multiplier * m + addend) / divisor^shortcut  with  m % divisor != 0
+                let AffineMap {
+                    multiplier,
+                    addend,
+                    divisor,
+                    shortcut,
+                } = self.map;
+                let target = if shortcut {
+                    n.mul_add(divisor, 0)
+                } else {
+                    Some(n.clone())
+                };
+                let up2 = target
+                    .filter(|t| t.rem_u64(multiplier) == addend % multiplier)
+                    .and_then(|t| t.sub_u64(addend))
+                    .map(|t| t.div_u64(multiplier))
+                    .filter(|m| m.rem_u64(divisor) != 0);
+                (n.mul_add(divisor, 0), up2)
             }
-            CollatzKind::Compact => {
-                // n = ((24m + 5) - 1) / 4 = 6m + 1     =>  n % 6 == 1
-                // n = ((96m + 85) - 5) / 16 = 6m + 5   =>  n % 6 == 5
-                // n = (3(12m + 7) + 1) / 2 = 18m + 11  =>  n % 18 == 11
-                // n = (3(12m + 11) + 1) / 2 = 18m + 17 =>  n % 18 == 17
-                // n = (3(24m + 1) + 1) / 4 = 18m + 1   =>  n % 18 == 1
-                // n = (3(24m + 17) + 1) / 4 = 18m + 13 =>  n % 18 == 13
-                // n = (3(48m + 13) + 1) / 8 = 18m + 5  =>  n % 18 == 5
-                // n = (3(96m + 37) + 1) / 16 = 18m + 7 =>  n % 18 == 7
-                match n % 18 {
-                    11 | 17 => (n * 16 + 5, Some(n * 2 / 3)), // (n * 2 - 1) / 3
-                    1 | 13 => (n * 4 + 1, Some(n * 4 / 3)),   // (n * 4 - 1) / 3
-                    5 => (n * 16 + 5, Some(n * 8 / 3)),       // (n * 8 - 1) / 3
-                    7 => (n * 4 + 1, Some(n * 16 / 3)),       // (n * 16 - 1) / 3
-                    _ => unreachable!(),
+        }
+    }
+
+    /// Same as [`Collatz::generate_fill`] for `1..=max`.
+    pub fn generate_fill_down(&mut self, max: T) -> Result<(), CollatzError<T>> {
+        let end = max.mul_add(1, 1).ok_or(CollatzError::Overflow {
+            start: max.clone(),
+            step: 0,
+        })?;
+        self.generate_fill(T::from_u64(1)..end)
+    }
+
+    /// Adds the orbits of every start value in `range` that the map is defined on.
+    ///
+    /// Only the parts of `range` that are not filled yet are generated, from the top down,
+    /// and `range` is only recorded as filled once all of it is.
+    pub fn generate_fill(&mut self, range: Range<T>) -> Result<(), CollatzError<T>> {
+        self.step_fill(&mut FillProgress::new(range), usize::MAX)
+    }
+
+    /// Checks that every value in the domain from 1 to `max` reaches a cycle, without adding
+    /// their orbits to the tree.
+    ///
+    /// Values are checked in increasing order, by following their orbits until they drop
+    /// below where they start, and those in the classes of a [`Sieve`] with `k` digits are
+    /// skipped. An orbit that ends in a new cycle is added to the tree instead. On an error,
+    /// the values before the one that failed stay checked.
+    ///
+    /// Fails with `CollatzError::InvalidSieve` if `k` is 0 or the sieve would have more than
+    /// 2^24 entries.
+    pub fn generate_fill_sieve(
+        &mut self,
+        max: T,
+        k: u32,
+    ) -> Result<SieveReport, CollatzError<T>> {
+        let sieve = Sieve::new(self.kind, self.map, k).ok_or(CollatzError::InvalidSieve { k })?;
+        let mut report = SieveReport::default();
+        let mut n = self.verified_end().clone();
+        while n <= max {
+            if self.in_domain(&n) {
+                if sieve.descends(&n) {
+                    report.skipped += 1;
+                } else {
+                    self.check_descent(&n)?;
+                    report.checked += 1;
                 }
             }
+            n = n.mul_add(1, 1).ok_or(CollatzError::Overflow {
+                start: n.clone(),
+                step: 0,
+            })?;
+            self.verified_end = n.clone();
+        }
+        Ok(report)
+    }
+
+    /// Every value in the domain from 1 up to, but excluding, this is known to reach a cycle
+    /// of the tree, either by being in the tree or by [`Collatz::generate_fill_sieve`].
+    pub fn verified_end(&self) -> &T {
+        let one = T::from_u64(1);
+        match self.ranges.iter().find(|r| r.contains(&one)) {
+            Some(filled) => (&self.verified_end).max(&filled.end),
+            None => &self.verified_end,
         }
     }
 
-    pub fn generate_fill_down(&mut self, max: u64) {
-        for n in (self.ranges[0].end..=max).rev() {
-            match self.kind {
-                CollatzKind::Odd | CollatzKind::Compact if n % 2 == 0 => continue,
-                CollatzKind::Compact if n % 3 == 0 => continue,
-                _ => (),
+    /// Follows the orbit of `n` until it drops below `n`, using Brent's algorithm to find a
+    /// new cycle if it does not, in which case the orbit is added to the tree.
+    fn check_descent(&mut self, n: &T) -> Result<(), CollatzError<T>> {
+        let mut tortoise = n.clone();
+        let mut hare = n.clone();
+        let mut power = 1;
+        let mut length = 0;
+        for step in 0.. {
+            hare = self.checked_down(&hare).ok_or(CollatzError::Overflow {
+                start: n.clone(),
+                step,
+            })?;
+            if hare.magnitude_cmp(n) == Ordering::Less {
+                return Ok(());
+            }
+            if hare == tortoise {
+                break;
+            }
+            length += 1;
+            if length == power {
+                tortoise = hare.clone();
+                power *= 2;
+                length = 0;
             }
-            self.generate_down(n);
         }
-        self.ranges[0].end = max + 1;
+        self.generate_down(n.clone())
     }
 
-    pub fn generate_down(&mut self, mut n: u64) {
+    /// Adds the orbit of `n` to the tree.
+    ///
+    /// If a value of the orbit overflows `T`, the tree is left unchanged and the step
+    /// that overflowed is returned. The same goes for running out of the memory budget.
+    pub fn generate_down(&mut self, mut n: T) -> Result<(), CollatzError<T>> {
+        // Filled ranges also hold the values outside the domain, so check that first
+        if !self.in_domain(&n) {
+            return Err(CollatzError::NotInDomain(n));
+        }
         if self.contains(&n) {
             // Tree already contains `n`.
-            return;
+            return Ok(());
         }
+        let start = n.clone();
+        let first_new = self.arena.len();
+        let outside = self.steps_outside(&n);
+        let mut step = 0;
         let mut prev_node: Option<Node> = None;
-        unsafe {
-            while !self.contains(&n) {
-                // Create a new node
-                let new_node = NonNull::new_unchecked(Box::into_raw(Box::new(CollatzNode::new(n))));
-
-                // Doubly link this and the previous node, if Some(_)
-                if let Some(prev_node) = prev_node {
-                    (*prev_node.as_ptr()).down = Some(new_node);
-                    (*new_node.as_ptr()).up1 = Some(prev_node);
+        while step < outside || !self.contains(&n) {
+            if let Err(err) = self.check_memory_budget() {
+                self.remove_new_nodes(first_new);
+                return Err(err);
+            }
+            prev_node = Some(self.push_pending(n.clone(), prev_node));
+
+            n = match self.checked_down(&n) {
+                Some(n) => n,
+                None => {
+                    self.remove_new_nodes(first_new);
+                    return Err(CollatzError::Overflow { start, step });
                 }
+            };
+            step += 1;
+        }
+        // `start` was not in the tree, so at least one node was created
+        let last = prev_node.unwrap();
 
-                self.nodes.insert(n, new_node);
+        // Merge created nodes to the found node
+        let merge_node = self.get_node(&n).unwrap();
 
-                n = self.down(n);
+        if self.node(merge_node).data.depth == PENDING {
+            // The orbit ran into itself
+            self.add_cycle(merge_node, last);
+            return Ok(());
+        }
 
-                prev_node = Some(new_node);
+        self.link_pending(last, merge_node);
+        Ok(())
+    }
+
+    /// Creates a node for `value` whose orbit stats are not known yet, doubly linked to
+    /// `prev_node` if Some(_).
+    fn push_pending(&mut self, value: T, prev_node: Option<Node>) -> Node {
+        let mut new_node = CollatzNode::new(value);
+        new_node.data.depth = PENDING;
+        new_node.up1 = prev_node;
+        let new_node = self.push_node(new_node);
+        if let Some(prev_node) = prev_node {
+            self.node_mut(prev_node).down = Some(new_node);
+        }
+        new_node
+    }
+
+    /// Links the chain of new nodes ending in `last` to `merge_node` in the tree, and sets
+    /// their orbit stats.
+    fn link_pending(&mut self, last: Node, merge_node: Node) {
+        self.node_mut(last).down = Some(merge_node);
+        self.node_mut(merge_node).link_up(last);
+
+        // Trace back to set the orbit stats of newly created nodes
+        let mut prev_node = Some(last);
+        while let Some(node) = prev_node {
+            self.update_stats(node);
+            // All nodes are new, so they are linked to `up1`
+            prev_node = self.node(node).up1;
+        }
+    }
+
+    /// Number of steps the orbit of `n`, which is not in the tree, is known to take before it
+    /// reaches the tree, by jumping ahead with the jump table until it lands in the tree.
+    ///
+    /// This is 0 without a jump table, or if the orbit does not land in the tree within a
+    /// bounded number of steps, as when it ends in a new cycle.
+    fn steps_outside(&self, n: &T) -> usize {
+        const MAX_STEPS: usize = 1 << 16;
+        let table = match &self.jump_table {
+            Some(table) => table,
+            None => return 0,
+        };
+        let mut steps = 0;
+        let mut n = n.clone();
+        while steps < MAX_STEPS {
+            let (next, jump) = match table.jump(&n) {
+                Some(jump) => jump,
+                None => return 0,
+            };
+            if self.contains(&next) {
+                // The orbit merges with the tree somewhere after `n`, at most `jump` steps on
+                return steps + 1;
             }
+            n = next;
+            steps += jump;
+        }
+        0
+    }
 
-            // Merge created nodes to the found node
-            let merge_node = self.get_node(n).unwrap();
+    /// Sets the orbit stats of `node` from those of the node below it, which have to be
+    /// set already.
+    fn update_stats(&mut self, node: Node) {
+        let value = &self.node(node).data.value;
+        let down = self.node(self.node(node).down.unwrap());
+
+        let (highest_point, peak_step) = match down.data.highest_point.magnitude_cmp(value) {
+            Ordering::Less => (value.clone(), 0),
+            _ => (down.data.highest_point.clone(), down.data.peak_step + 1),
+        };
+
+        // Once the orbit reaches the root it only visits the cycle, where the root is
+        // closest to zero
+        let mut stopping_time = None;
+        let mut step = Some(down);
+        let mut steps = 1;
+        while let Some(next) = step {
+            if next.data.value.magnitude_cmp(value) == Ordering::Less {
+                stopping_time = Some(steps);
+                break;
+            }
+            step = next.down.map(|next| self.node(next));
+            steps += 1;
+        }
 
-            if let Some(prev_node) = prev_node {
-                (*prev_node.as_ptr()).down = Some(merge_node);
-                if (*merge_node.as_ptr()).up1.is_none() {
-                    (*merge_node.as_ptr()).up1 = Some(prev_node);
-                } else {
-                    (*merge_node.as_ptr()).up2 = Some(prev_node);
+        let data = NodeData {
+            value: value.clone(),
+            depth: down.data.depth + 1,
+            highest_point,
+            stopping_time,
+            odd_steps: down.data.odd_steps + self.is_odd_step(value) as usize,
+            peak_step,
+        };
+        self.node_mut(node).data = data;
+    }
+
+    /// Whether the step from `n` multiplies, rather than only divides.
+    fn is_odd_step(&self, n: &T) -> bool {
+        match self.kind {
+            CollatzKind::Full | CollatzKind::Short => n.rem_u64(2) == 1,
+            // Every value is odd, but the shortcut steps skip to a smaller predecessor
+            CollatzKind::Odd | CollatzKind::Compact => {
+                self.rules.as_ref().unwrap().is_odd_step(n)
+            }
+            CollatzKind::Affine => n.rem_u64(self.map.divisor) != 0,
+        }
+    }
+
+    /// Turns the chain of new nodes ending in `last`, whose next value is `entry`, into a
+    /// new tree rooted at the value of the cycle closest to zero.
+    fn add_cycle(&mut self, entry: Node, last: Node) {
+        let mut cycle = vec![last];
+        while *cycle.last().unwrap() != entry {
+            cycle.push(self.node(*cycle.last().unwrap()).up1.unwrap());
+        }
+        cycle.reverse();
+
+        // Close the cycle, then cut it open again below its value closest to zero
+        self.node_mut(last).down = Some(entry);
+        self.node_mut(entry).link_up(last);
+        let root_index = (0..cycle.len())
+            .min_by(|&a, &b| {
+                self.node(cycle[a])
+                    .data
+                    .value
+                    .magnitude_cmp(&self.node(cycle[b]).data.value)
+            })
+            .unwrap();
+        let root = cycle[root_index];
+        let down = self.node_mut(root).down.take().unwrap();
+        self.node_mut(down).unlink_up(root);
+
+        let value = self.node(root).data.value.clone();
+        self.node_mut(root).data = NodeData::new(value);
+        let mut node_stack = vec![root];
+        while let Some(node) = node_stack.pop() {
+            let node = self.node(node);
+            for &up in [node.up1, node.up2].iter().flatten() {
+                self.update_stats(up);
+                node_stack.push(up);
+            }
+        }
+
+        cycle.rotate_left(root_index);
+        self.roots.push(root);
+        // The inverse tree of the new root has not been walked
+        self.up_filled = None;
+        let cycle = cycle
+            .into_iter()
+            .map(|node| self.node(node).data.value.clone())
+            .collect();
+        self.cycles.push(cycle);
+    }
+
+    /// Frees the nodes created by an unfinished `generate_down`, which take up the arena
+    /// from `first` on.
+    fn remove_new_nodes(&mut self, first: usize) {
+        for node in self.arena.drain(first..) {
+            self.nodes.remove(&node.data.value);
+        }
+    }
+
+    /// Removes every node that is not on the orbit of a value in `range`, except for the
+    /// roots, and returns the number of bytes freed.
+    pub fn prune(&mut self, range: RangeInclusive<T>) -> usize {
+        let before = self.memory_usage();
+
+        // Mark the nodes to keep
+        let mut keep = vec![false; self.arena.len()];
+        for &root in &self.roots {
+            keep[root as usize] = true;
+        }
+        for node in 0..self.arena.len() {
+            if !range.contains(&self.arena[node].data.value) {
+                continue;
+            }
+            let mut node = Some(node as Node);
+            while let Some(n) = node.filter(|&n| !keep[n as usize]) {
+                keep[n as usize] = true;
+                node = self.node(n).down;
+            }
+        }
+
+        // Move the kept nodes to a new arena, dropping links to removed nodes
+        let mut new_index = vec![None; keep.len()];
+        let mut len = 0;
+        for (node, _) in keep.iter().enumerate().filter(|(_, &keep)| keep) {
+            new_index[node] = Some(len as Node);
+            len += 1;
+        }
+        let remap = |node: Option<Node>| node.and_then(|node| new_index[node as usize]);
+        let arena = std::mem::replace(&mut self.arena, Vec::with_capacity(len));
+        self.nodes = HashMap::with_capacity(len);
+        for (mut node, _) in arena.into_iter().zip(&keep).filter(|(_, &keep)| keep) {
+            let (up1, up2) = (remap(node.up1), remap(node.up2));
+            node.down = remap(node.down);
+            node.up1 = up1.or(up2);
+            node.up2 = up1.and(up2);
+            self.push_node(node);
+        }
+        for root in &mut self.roots {
+            *root = new_index[*root as usize].unwrap();
+        }
+
+        // Only the parts of the filled ranges inside `range` are left
+        let end = range.end().mul_add(1, 1);
+        for filled in &mut self.ranges {
+            filled.start = filled.start.clone().max(range.start().clone());
+            if let Some(end) = &end {
+                filled.end = filled.end.clone().min(end.clone());
+            }
+        }
+        self.ranges.retain(|filled| filled.start < filled.end);
+        self.up_filled = None;
+
+        before.saturating_sub(self.memory_usage())
+    }
+
+    /// Creates a node for `value` as a predecessor of `node`.
+    fn add_up(&mut self, node: Node, value: T) -> Node {
+        let mut new_node = CollatzNode::new(value);
+        new_node.down = Some(node);
+        let new_node = self.push_node(new_node);
+        self.node_mut(node).link_up(new_node);
+        self.update_stats(new_node);
+        new_node
+    }
+
+    /// Walks the inverse tree breadth first from every root, adding each predecessor that is
+    /// at most `max` away from zero. With `max_depth`, nodes deeper than that are not added.
+    ///
+    /// Predecessors already in the tree, for instance from [`Collatz::generate_down`], are
+    /// walked through even when they are beyond `max`.
+    pub fn generate_up(
+        &mut self,
+        max: T,
+        max_depth: Option<usize>,
+    ) -> Result<(), CollatzError<T>> {
+        let mut node_queue: VecDeque<Node> = self.roots.iter().copied().collect();
+        while let Some(node) = node_queue.pop_front() {
+            if max_depth.is_some_and(|max_depth| self.node(node).data.depth >= max_depth) {
+                continue;
+            }
+
+            // A predecessor overflowing `T` is beyond any `max`
+            let (up1, up2) = self.checked_up(&self.node(node).data.value);
+            for up in up1.into_iter().chain(up2) {
+                match self.get_node(&up) {
+                    // The root closes its cycle, and was walked first
+                    Some(existing) if self.node(existing).down.is_none() => (),
+                    Some(existing) => node_queue.push_back(existing),
+                    None if up.magnitude_cmp(&max) == Ordering::Greater => (),
+                    None => {
+                        self.check_memory_budget()?;
+                        node_queue.push_back(self.add_up(node, up));
+                    }
                 }
             }
+        }
+        if max_depth.is_none() {
+            let filled = self.up_filled.take().into_iter().chain(Some(max));
+            self.up_filled = filled.max_by(|a, b| a.magnitude_cmp(b));
+        }
+        Ok(())
+    }
+
+    /// Number of predecessors of `n`, that is values other than `n` whose orbit reaches it,
+    /// from 1 up to `max`, or from `max` up to -1 for a negative `n`.
+    ///
+    /// Fills the tree with every start value in that range first, as
+    /// [`Collatz::generate_fill`] does, and with `n` itself.
+    pub fn count_predecessors(&mut self, n: T, max: &T) -> Result<usize, CollatzError<T>> {
+        let zero = T::from_u64(0);
+        let window = if n > zero {
+            let end = max.mul_add(1, 1).ok_or(CollatzError::Overflow {
+                start: max.clone(),
+                step: 0,
+            })?;
+            T::from_u64(1)..end
+        } else {
+            max.clone()..zero
+        };
+        self.generate_fill(window.clone())?;
+        self.generate_down(n.clone())?;
+
+        // Every value of the tree reaches the whole cycle it ends in
+        let node = self.get_node(&n).unwrap();
+        let start = match self.cycle_of(&n) {
+            Some(cycle) if self.cycles[cycle].contains(&n) => self.roots[cycle],
+            _ => node,
+        };
+        let counted = |m: &T| *m != n && window.contains(m);
+        Ok(counted(&self.node(start).data.value) as usize
+            + self.count_up_linked(start, |_| true, counted))
+    }
 
-            // Trace back to set the depth and highest_point of newly created nodes
-            let mut depth = (*merge_node.as_ptr()).data.depth;
-            let mut highest_point = (*merge_node.as_ptr()).data.highest_point;
-            while let Some(node) = prev_node {
-                depth += 1;
-                highest_point = highest_point.max((*node.as_ptr()).data.value);
-                (*node.as_ptr()).data.depth = depth;
-                (*node.as_ptr()).data.highest_point = highest_point;
-                // All nodes are new, so they are linked to `up1`
-                prev_node = (*node.as_ptr()).up1;
+    /// Number of predecessors of `n` which are at most `max` away from zero and reach `n`
+    /// through such values only, as [`Collatz::generate_up`] adds them. This is a lower
+    /// bound on [`Collatz::count_predecessors`] that does not need the tree. Values outside
+    /// the domain have none.
+    ///
+    /// Follows the links of the tree when `generate_up` has filled it in at least up to
+    /// `max`, and otherwise the predecessors given by [`Collatz::checked_up`], without adding
+    /// any nodes.
+    pub fn count_bounded_predecessors(&self, n: T, max: &T) -> usize {
+        let filled = self
+            .up_filled
+            .as_ref()
+            .is_some_and(|filled| filled.magnitude_cmp(max) != Ordering::Less);
+        match self.get_node(&n).filter(|_| filled) {
+            Some(node) => {
+                let within = |m: &T| m.magnitude_cmp(max) != Ordering::Greater;
+                self.count_up_linked(node, within, within)
+            }
+            None => {
+                let layers = self.layers_up(n, Some(max), None, usize::MAX);
+                layers.iter().sum::<usize>().saturating_sub(1)
             }
         }
     }
 
-    pub fn generate_up(&mut self, max: u64) {
-        let mut node_stack = vec![self.root];
+    /// Number of values whose orbit reaches `n` in at most `depth` steps, including `n`
+    /// itself, or 0 if `n` is not in the domain. Only the layers whose predecessors fit `T`
+    /// are counted, as in [`Collatz::inverse_layer_counts`].
+    pub fn subtree_size(&self, n: T, depth: usize) -> usize {
+        self.layers_up(n, None, Some(depth), usize::MAX).iter().sum()
+    }
+
+    /// Number of values whose orbit reaches `n` in exactly `0..=depth` steps, from the
+    /// predecessors given by [`Collatz::checked_up`], or nothing if `n` is not in the domain.
+    ///
+    /// From the root of the tree of `Full` or `Short`, this grows roughly like `(4/3)^depth`.
+    /// Other kinds grow faster, `Compact` doubling every layer, so the counts stop after the
+    /// first layer of more than 2^20 values. They also stop before the first layer that might
+    /// have a predecessor too far from zero for `T`, so every count is exact.
+    pub fn inverse_layer_counts(&self, n: T, depth: usize) -> Vec<usize> {
+        self.layers_up(n, None, Some(depth), MAX_LAYER_WIDTH)
+    }
+
+    /// Counts the `counted` nodes walked breadth first up from `node`, excluding `node`
+    /// itself, through the values `through` lets pass.
+    fn count_up_linked(
+        &self,
+        node: Node,
+        through: impl Fn(&T) -> bool,
+        counted: impl Fn(&T) -> bool,
+    ) -> usize {
+        let mut node_queue = VecDeque::from(vec![node]);
         let mut count = 0;
-        while !node_stack.is_empty() && count < 10 {
-            count += 1;
-            unsafe {
-                let node = node_stack.pop().unwrap();
-
-                if (*node.as_ptr()).up1.is_none() {
-                    let (up1, up2) = self.up((*node.as_ptr()).data.value);
-                    if up1 <= max && up1 != 1 {
-                        let new_node =
-                            NonNull::new_unchecked(Box::into_raw(Box::new(CollatzNode::new(up1))));
-                        (*new_node.as_ptr()).data.depth = (*node.as_ptr()).data.depth + 1;
-                        (*new_node.as_ptr()).data.highest_point = up1.max((*node.as_ptr()).data.highest_point);
-                        (*new_node.as_ptr()).down = Some(node);
-                        (*node.as_ptr()).up1 = Some(new_node);
-
-                        self.nodes.insert(up1, new_node);
-                        node_stack.push(new_node);
+        while let Some(node) = node_queue.pop_front() {
+            let node = self.node(node);
+            for up in node.up1.into_iter().chain(node.up2) {
+                let value = &self.node(up).data.value;
+                if through(value) {
+                    count += counted(value) as usize;
+                    node_queue.push_back(up);
+                }
+            }
+        }
+        count
+    }
+
+    /// Number of values at each number of steps up from `n`, starting with `n` itself, walked
+    /// through values at most `max` away from zero and at most `depth` steps up, from the
+    /// predecessors given by the rules. Stops after the first layer of more than `width`
+    /// values, and without `max`, before the first layer with a predecessor that might
+    /// overflow `T`, rather than leave it out.
+    fn layers_up(
+        &self,
+        n: T,
+        max: Option<&T>,
+        depth: Option<usize>,
+        width: usize,
+    ) -> Vec<usize> {
+        if !self.in_domain(&n) {
+            return Vec::new();
+        }
+        let mut layers = Vec::new();
+        let mut layer = vec![n.clone()];
+        while !layer.is_empty() {
+            layers.push(layer.len());
+            if depth.is_some_and(|depth| layers.len() > depth) || layer.len() > width {
+                break;
+            }
+            let factor = self.up_factor();
+            if max.is_none() && layer.iter().any(|m| m.mul_add(factor, factor).is_none()) {
+                break;
+            }
+            let mut next = Vec::new();
+            for m in &layer {
+                // A predecessor overflowing `T` is beyond any `max`
+                let (up1, up2) = self.checked_up(m);
+                for up in up1.into_iter().chain(up2) {
+                    // The root of a cycle, or `n` on a cycle not generated yet, closes the
+                    // cycle
+                    let closes_cycle = up == n
+                        || self
+                            .get_node(&up)
+                            .is_some_and(|node| self.node(node).down.is_none());
+                    let within =
+                        max.is_none_or(|max| up.magnitude_cmp(max) != Ordering::Greater);
+                    if !closes_cycle && within {
+                        next.push(up);
                     }
+                }
+            }
+            layer = next;
+        }
+        layers
+    }
 
-                    if let Some(up2) = up2 {
-                        if up2 > max || up2 == 1 {
-                            continue;
-                        }
+    /// Bound on how many times further from zero than `n` its predecessors are.
+    fn up_factor(&self) -> u64 {
+        match self.kind {
+            CollatzKind::Full | CollatzKind::Short => 2,
+            CollatzKind::Odd => 4,
+            // The shortcut parent 16n + 5 is the furthest
+            CollatzKind::Compact => 16,
+            CollatzKind::Affine => self.map.divisor,
+        }
+    }
 
-                        let new_node =
-                            NonNull::new_unchecked(Box::into_raw(Box::new(CollatzNode::new(up2))));
-                        (*new_node.as_ptr()).data.depth = (*node.as_ptr()).data.depth + 1;
-                        (*new_node.as_ptr()).data.highest_point = up2.max((*node.as_ptr()).data.highest_point);
-                        (*new_node.as_ptr()).down = Some(node);
-                        (*node.as_ptr()).up2 = Some(new_node);
+    /// Number of steps from `n` to the root of its tree, or `None` if `n` has not been
+    /// generated. See [`Collatz::depth`] to generate it on demand.
+    pub fn get_depth(&self, n: T) -> Option<usize> {
+        let node = self.get_node(&n)?;
+        Some(self.node(node).data.depth)
+    }
 
-                        self.nodes.insert(up2, new_node);
-                        node_stack.push(new_node);
-                    }
-                } else if (*node.as_ptr()).up2.is_none() {
-                    let existing = (*node.as_ptr()).up1.unwrap();
-                    node_stack.push(existing);
-
-                    let (up1, up2) = self.up((*node.as_ptr()).data.value);
-
-                    if (*existing.as_ptr()).data.value != up1 {
-                        // up2 is linked to up1, so link up1 to up2
-                        if up1 > max {
-                            continue;
-                        }
-
-                        let new_node =
-                            NonNull::new_unchecked(Box::into_raw(Box::new(CollatzNode::new(up1))));
-                        (*new_node.as_ptr()).data.depth = (*node.as_ptr()).data.depth + 1;
-                        (*new_node.as_ptr()).data.highest_point = up1.max((*node.as_ptr()).data.highest_point);
-                        (*node.as_ptr()).up2 = Some(new_node);
-                        (*new_node.as_ptr()).down = Some(node);
-
-                        self.nodes.insert(up1, new_node);
-                        node_stack.push(new_node);
-
-                        // No need to check `up2` anymore
-                        continue;
-                    }
+    /// The orbit stats of `n`, or `None` if `n` has not been generated.
+    pub fn get_data(&self, n: &T) -> Option<&NodeData<T>> {
+        Some(&self.node(self.get_node(n)?).data)
+    }
 
-                    if let Some(up2) = up2 {
-                        if up2 > max || up2 == 1 {
-                            continue;
-                        }
+    /// See [`NodeData::stopping_time`]. This is `None` if `n` has not been generated.
+    pub fn get_stopping_time(&self, n: T) -> Option<usize> {
+        self.get_data(&n)?.stopping_time
+    }
 
-                        let new_node =
-                            NonNull::new_unchecked(Box::into_raw(Box::new(CollatzNode::new(up2))));
-                        (*new_node.as_ptr()).data.depth = (*node.as_ptr()).data.depth + 1;
-                        (*new_node.as_ptr()).data.highest_point = up2.max((*node.as_ptr()).data.highest_point);
-                        (*new_node.as_ptr()).down = Some(node);
-                        (*node.as_ptr()).up2 = Some(new_node);
+    /// See [`NodeData::glide`]. This is `None` if `n` has not been generated.
+    pub fn get_glide(&self, n: T) -> Option<usize> {
+        self.get_data(&n)?.glide()
+    }
 
-                        self.nodes.insert(up2, new_node);
-                        node_stack.push(new_node);
-                    }
-                } else {
-                    node_stack.push((*node.as_ptr()).up1.unwrap());
-                    node_stack.push((*node.as_ptr()).up2.unwrap());
+    /// See [`NodeData::odd_steps`]. This is `None` if `n` has not been generated.
+    pub fn get_odd_steps(&self, n: T) -> Option<usize> {
+        Some(self.get_data(&n)?.odd_steps)
+    }
+
+    /// See [`NodeData::peak_step`]. This is `None` if `n` has not been generated.
+    pub fn get_peak_step(&self, n: T) -> Option<usize> {
+        Some(self.get_data(&n)?.peak_step)
+    }
+
+    /// Parity vector of the orbit of `n` down to the root of its tree, or `None` if `n` has
+    /// not been generated.
+    ///
+    /// For Odd and Compact this is the parity vector of the same orbit under Short, built
+    /// from [`Collatz::exponents`].
+    pub fn parity_vector(&self, n: T) -> Option<ParityVector> {
+        match self.kind {
+            CollatzKind::Odd | CollatzKind::Compact => {
+                Some(ParityVector::exponents_to_short(&self.exponents(n)?))
+            }
+            _ => {
+                let orbit = self.iter_orbit(n)?;
+                Some(orbit.map(|data| self.is_odd_step(&data.value)).collect())
+            }
+        }
+    }
+
+    /// Exponents of the orbit of `n` down to the root of its tree, where each step sends `m`
+    /// to `(3m + 1) / 2^k` for the largest possible `k`.
+    ///
+    /// The trees of Odd and Compact also link values that merely share their next value,
+    /// so this follows the actual orbit instead. Returns `None` for other kinds, if `n` has
+    /// not been generated, or if the orbit overflows `T`.
+    pub fn exponents(&self, n: T) -> Option<Vec<u32>> {
+        if !matches!(self.kind, CollatzKind::Odd | CollatzKind::Compact) {
+            return None;
+        }
+        let cycle = &self.cycles[self.cycle_of(&n)?];
+        let mut exponents = Vec::new();
+        let mut n = n;
+        let mut steps_in_cycle = 0;
+        while n != cycle[0] {
+            // Give up if the orbit goes around the cycle without meeting the root
+            if cycle.contains(&n) {
+                steps_in_cycle += 1;
+                if steps_in_cycle > cycle.len() {
+                    return None;
                 }
             }
+            let mut m = n.mul_add(3, 1)?;
+            let mut k = 0;
+            while m.rem_u64(2) == 0 {
+                m = m.div_u64(2);
+                k += 1;
+            }
+            exponents.push(k);
+            n = m;
+        }
+        Some(exponents)
+    }
+
+    /// The first value the orbits of `a` and `b` have in common, or `None` if either has
+    /// not been generated or they end in different cycles. See
+    /// [`Collatz::common_ancestor`] to generate them on demand.
+    pub fn find_common_ancestor(&self, a: T, b: T) -> Option<T> {
+        let node_a = self.get_node(&a)?;
+        let node_b = self.get_node(&b)?;
+
+        let node = self.find_common_ancestor_node(node_a, node_b)?;
+        Some(self.node(node).data.value.clone())
+    }
+
+    fn find_common_ancestor_node(&self, mut a: Node, mut b: Node) -> Option<Node> {
+        if let Some(ancestors) = &self.ancestors {
+            return ancestors.common_ancestor(&self.arena, a, b);
+        }
+        while self.node(a).data.depth < self.node(b).data.depth {
+            b = self.node(b).down?;
+        }
+        while self.node(a).data.depth > self.node(b).data.depth {
+            a = self.node(a).down?;
+        }
+        while a != b {
+            // Both reach their root at the same time if the trees differ
+            a = self.node(a).down?;
+            b = self.node(b).down?;
+        }
+        Some(a)
+    }
+
+    /// Where the orbits of `a` and `b` meet, or `None` if either has not been generated or
+    /// they end in different cycles. See [`Collatz::merge`] to generate them on demand.
+    pub fn find_merge(&self, a: T, b: T) -> Option<Merge<T>> {
+        self.find_merge_all(&[a, b])
+    }
+
+    /// Same as [`Collatz::find_merge`], for the orbits of all of `values`. Returns `None`
+    /// if there are no values.
+    pub fn find_merge_all(&self, values: &[T]) -> Option<Merge<T>> {
+        let nodes = values
+            .iter()
+            .map(|n| self.get_node(n))
+            .collect::<Option<Vec<_>>>()?;
+        let (&first, rest) = nodes.split_first()?;
+        let merge = rest.iter().try_fold(first, |merge, &node| {
+            self.find_common_ancestor_node(merge, node)
+        })?;
+
+        let merge_depth = self.node(merge).data.depth;
+        let steps = nodes
+            .iter()
+            .map(|&node| self.node(node).data.depth - merge_depth)
+            .collect();
+        // Count each edge once, where the orbits share it before they reach `merge`
+        let mut visited = HashSet::new();
+        let mut distance = 0;
+        for mut node in nodes {
+            while node != merge && visited.insert(node) {
+                node = self.node(node).down.unwrap();
+                distance += 1;
+            }
         }
+        Some(Merge {
+            value: self.node(merge).data.value.clone(),
+            steps,
+            distance,
+        })
     }
 
-    // NOTE: Should the orbit of `n` be calculated instead of panicking?
-    pub fn get_depth(&self, n: u64) -> usize {
-        let node = self
-            .get_node(n)
-            .expect("the requested node has not yet been generated");
-        unsafe { (*node.as_ptr()).data.depth }
+    /// Indexes the current tree so that [`Collatz::find_common_ancestor`] takes
+    /// `O(log depth)` steps instead of `O(depth)`, at the cost of a few bytes per node and
+    /// level of the index.
+    ///
+    /// The index is dropped as soon as the tree changes, and has to be built again.
+    pub fn index_ancestors(&mut self) {
+        self.ancestors = Some(AncestorIndex::new(&self.arena));
     }
 
-    // NOTE: Should the orbit of `a` and `b` be calculated instead of panicking?
-    pub fn find_common_ancestor(&self, a: u64, b: u64) -> u64 {
-        let node_a = self
-            .get_node(a)
-            .expect("the requested node has not yet been generated");
-        let node_b = self
-            .get_node(b)
-            .expect("the requested node has not yet been generated");
+    pub fn has_ancestor_index(&self) -> bool {
+        self.ancestors.is_some()
+    }
 
-        unsafe {
-            let node = self.find_common_ancestor_unsafe(node_a, node_b);
-            (*node.as_ptr()).data.value
+    /// Indexes the current tree by depth, so that [`Collatz::values_at_depth`] and
+    /// [`Collatz::layer_counts`] do not go through every node.
+    ///
+    /// The index is dropped as soon as the tree changes, and has to be built again.
+    pub fn index_depths(&mut self) {
+        let mut layers: Vec<Vec<Node>> = Vec::new();
+        for (node, data) in self.arena.iter().enumerate() {
+            let depth = data.data.depth;
+            if layers.len() <= depth {
+                layers.resize_with(depth + 1, Vec::new);
+            }
+            layers[depth].push(node as Node);
         }
+        self.depth_index = Some(layers);
+    }
+
+    pub fn has_depth_index(&self) -> bool {
+        self.depth_index.is_some()
+    }
+
+    /// Values of the tree that take `depth` steps to reach the root of their tree, in no
+    /// particular order.
+    ///
+    /// Once the tree is filled up to `max`, the values up to `max` among these are all those
+    /// with a total stopping time of `depth` steps of this kind.
+    pub fn values_at_depth(&self, depth: usize) -> impl Iterator<Item = &T> + '_ {
+        let nodes = match &self.depth_index {
+            Some(layers) => Either::Left(layers.get(depth).into_iter().flatten().copied()),
+            None => Either::Right(
+                (0..self.arena.len() as Node)
+                    .filter(move |&node| self.node(node).data.depth == depth),
+            ),
+        };
+        nodes.map(move |node| &self.node(node).data.value)
     }
 
-    unsafe fn find_common_ancestor_unsafe(&self, mut a: Node, mut b: Node) -> Node {
-        while (*a.as_ptr()).data.depth < (*b.as_ptr()).data.depth {
-            b = (*b.as_ptr()).down.unwrap();
+    /// Number of nodes of the tree at each depth.
+    ///
+    /// See [`Collatz::inverse_layer_counts`] for the whole layers of the inverse tree, which
+    /// only a tree from [`Collatz::generate_up`] without a `max` has.
+    pub fn layer_counts(&self) -> Vec<usize> {
+        if let Some(layers) = &self.depth_index {
+            return layers.iter().map(Vec::len).collect();
         }
-        while (*a.as_ptr()).data.depth > (*b.as_ptr()).data.depth {
-            a = (*a.as_ptr()).down.unwrap();
+        let mut counts = Vec::new();
+        for node in &self.arena {
+            let depth = node.data.depth;
+            if counts.len() <= depth {
+                counts.resize(depth + 1, 0);
+            }
+            counts[depth] += 1;
         }
-        while (*a.as_ptr()).data.value != (*b.as_ptr()).data.value {
-            a = (*a.as_ptr()).down.unwrap();
-            b = (*b.as_ptr()).down.unwrap();
+        counts
+    }
+
+    /// The orbit of `n` up to the root of its tree, or `None` if `n` has not been
+    /// generated. See [`Collatz::orbit`] to generate it on demand.
+    pub fn iter_orbit(&self, n: T) -> Option<IterOrbit<'_, T>> {
+        Some(IterOrbit::new(&self.arena, self.get_node(&n)?))
+    }
+
+    /// The orbit of `n` one step at a time, including the cycle it ends in, without adding
+    /// anything to the tree. Returns `None` if `n` is not in the domain.
+    pub fn stream_orbit(&self, n: T) -> Option<StreamOrbit<'_, T>> {
+        if self.in_domain(&n) {
+            Some(StreamOrbit::new(self, n))
+        } else {
+            None
         }
-        return a;
     }
 
-    pub fn iter_orbit(&self, n: u64) -> IterOrbit {
-        unsafe { IterOrbit::new(self.get_node(n).unwrap().as_ref()) }
+    /// Same as [`Collatz::get_depth`], generating the orbit of `n` first if needed.
+    pub fn depth(&mut self, n: T) -> Result<usize, CollatzError<T>> {
+        self.generate_down(n.clone())?;
+        Ok(self.get_depth(n).unwrap())
     }
-    pub fn iter(&self) -> Iter {
-        Iter::new(&self)
+
+    /// Same as [`Collatz::find_common_ancestor`], generating the orbits of `a` and `b`
+    /// first if needed.
+    pub fn common_ancestor(&mut self, a: T, b: T) -> Result<Option<T>, CollatzError<T>> {
+        self.generate_down(a.clone())?;
+        self.generate_down(b.clone())?;
+        Ok(self.find_common_ancestor(a, b))
+    }
+
+    /// Same as [`Collatz::find_merge`], generating the orbits of `a` and `b` first if needed.
+    pub fn merge(&mut self, a: T, b: T) -> Result<Option<Merge<T>>, CollatzError<T>> {
+        self.merge_all(&[a, b])
+    }
+
+    /// Same as [`Collatz::find_merge_all`], generating the orbits of `values` first if
+    /// needed.
+    pub fn merge_all(&mut self, values: &[T]) -> Result<Option<Merge<T>>, CollatzError<T>> {
+        for n in values {
+            self.generate_down(n.clone())?;
+        }
+        Ok(self.find_merge_all(values))
+    }
+
+    /// Same as [`Collatz::iter_orbit`], generating the orbit of `n` first if needed.
+    pub fn orbit(&mut self, n: T) -> Result<IterOrbit<'_, T>, CollatzError<T>> {
+        self.generate_down(n.clone())?;
+        Ok(self.iter_orbit(n).unwrap())
+    }
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter::new(self)
+    }
+}
+
+/// `n * k / 3` rounded down, without overflowing on `n * k`.
+fn mul_div3<T: Value>(n: &T, k: u64) -> Option<T> {
+    n.div_u64(3).mul_add(k, k * n.rem_u64(3) / 3)
+}
+
+impl<'a, T: Value> IntoIterator for &'a Collatz<T> {
+    type Item = &'a NodeData<T>;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
     }
 }
 
-struct IterOrbit<'a> {
-    current_node: &'a CollatzNode,
+pub struct IterOrbit<'a, T> {
+    arena: &'a [CollatzNode<T>],
+    current_node: Node,
 }
 
-impl<'a> IterOrbit<'a> {
-    fn new(node: &'a CollatzNode) -> IterOrbit<'a> {
+impl<'a, T> IterOrbit<'a, T> {
+    fn new(arena: &'a [CollatzNode<T>], node: Node) -> IterOrbit<'a, T> {
         Self {
+            arena,
             current_node: node,
         }
     }
 }
 
-impl<'a> Iterator for IterOrbit<'a> {
-    type Item = &'a NodeData;
+impl<'a, T> Iterator for IterOrbit<'a, T> {
+    type Item = &'a NodeData<T>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        unsafe {
-            if self.current_node.down.is_none() {
-                return None;
-            }
-            let data = &self.current_node.data;
-            self.current_node = self.current_node.down.unwrap().as_ref();
-            Some(data)
-        }
+        let node = &self.arena[self.current_node as usize];
+        self.current_node = node.down?;
+        Some(&node.data)
     }
 }
 
-struct Iter<'a> {
-    current_node: &'a CollatzNode,
-    stack: VecDeque<&'a CollatzNode>,
+pub struct Iter<'a, T> {
+    arena: &'a [CollatzNode<T>],
+    stack: VecDeque<Node>,
 }
 
-impl<'a> Iter<'a> {
-    fn new(collatz: &'a Collatz) -> Self {
-        unsafe {
-            let head_node = collatz.root.as_ref();
-            Iter {
-                current_node: head_node,
-                stack: VecDeque::from([head_node]),
-            }
+impl<'a, T> Iter<'a, T> {
+    fn new(collatz: &'a Collatz<T>) -> Self {
+        Iter {
+            arena: &collatz.arena,
+            stack: collatz.roots.iter().copied().collect(),
         }
     }
 }
 
-impl<'a> Iterator for Iter<'a> {
-    type Item = &'a NodeData;
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a NodeData<T>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.stack.is_empty() {
-            return None;
-        }
-        self.current_node = self.stack.pop_front().unwrap();
-
-        let node = self.current_node;
-        unsafe {
-            if let Some(node) = node.up1 {
-                self.stack.push_back(node.as_ref());
-            }
-            if let Some(node) = node.up2 {
-                self.stack.push_back(node.as_ref());
-            }
-        }
+        let node = &self.arena[self.stack.pop_front()? as usize];
+        self.stack.extend(node.up1);
+        self.stack.extend(node.up2);
 
         Some(&node.data)
     }
@@ -492,7 +1465,11 @@ impl<'a> Iterator for Iter<'a> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use itertools;
+
+    /// The depth, value and highest point of `node`
+    fn summary<T: Clone>(node: &NodeData<T>) -> (usize, T, T) {
+        (node.depth, node.value.clone(), node.highest_point.clone())
+    }
 
     #[test]
     fn single_down_full() {
@@ -530,6 +1507,42 @@ mod test {
         assert_eq!(collatz.down(11), 17);
     }
 
+    #[test]
+    fn single_down_affine() {
+        let collatz = Collatz::<u64>::with_map(AffineMap::new(5, 1, 2, false).unwrap());
+        assert_eq!(collatz.down(1), 6);
+        assert_eq!(collatz.down(5), 26);
+        assert_eq!(collatz.down(26), 13);
+        assert_eq!(collatz.down(13), 66);
+
+        let collatz = Collatz::<u64>::with_map(AffineMap::new(3, 5, 2, true).unwrap());
+        assert_eq!(collatz.down(1), 4);
+        assert_eq!(collatz.down(4), 2);
+        assert_eq!(collatz.down(7), 13);
+    }
+
+    #[test]
+    fn affine_matches_full_and_short() {
+        let full = Collatz::new(CollatzKind::Full);
+        let short = Collatz::new(CollatzKind::Short);
+        let affine_full = Collatz::new(CollatzKind::Affine);
+        let affine_short = Collatz::with_map(AffineMap::new(3, 1, 2, true).unwrap());
+        for n in 1..1000 {
+            assert_eq!(affine_full.down(n), full.down(n));
+            assert_eq!(affine_full.up(n), full.up(n));
+            assert_eq!(affine_short.down(n), short.down(n));
+            assert_eq!(affine_short.up(n), short.up(n));
+        }
+    }
+
+    #[test]
+    fn invalid_affine_map() {
+        assert!(AffineMap::new(0, 1, 2, false).is_err());
+        assert!(AffineMap::new(3, 1, 1, false).is_err());
+        assert!(AffineMap::new(3, 1, 3, true).is_err());
+        assert!(AffineMap::new(7, 1, 3, false).is_ok());
+    }
+
     #[test]
     fn single_up_full() {
         let collatz = Collatz::default();
@@ -576,36 +1589,53 @@ mod test {
         assert_eq!(collatz.up(17), (277, Some(11)));
     }
 
+    #[test]
+    fn single_up_affine() {
+        let collatz = Collatz::<u64>::with_map(AffineMap::new(5, 1, 2, false).unwrap());
+        assert_eq!(collatz.up(1), (2, None));
+        assert_eq!(collatz.up(6), (12, Some(1)));
+        assert_eq!(collatz.up(13), (26, None));
+        assert_eq!(collatz.up(16), (32, Some(3)));
+        assert_eq!(collatz.up(26), (52, Some(5)));
+
+        let collatz = Collatz::<u64>::with_map(AffineMap::new(7, 2, 3, false).unwrap());
+        assert_eq!(collatz.up(9), (27, Some(1)));
+        assert_eq!(collatz.up(10), (30, None));
+        assert_eq!(collatz.up(16), (48, Some(2)));
+        assert_eq!(collatz.up(23), (69, None));
+        assert_eq!(collatz.up(30), (90, Some(4)));
+    }
+
     #[test]
     fn generate_single_down() {
         let mut collatz = Collatz::default();
-        collatz.generate_down(6);
+        collatz.generate_down(6).unwrap();
         itertools::assert_equal(
-            collatz.iter(),
+            collatz.iter().map(summary),
             [
-                (0, 1, 1).into(),
-                (1, 2, 2).into(),
-                (2, 4, 4).into(),
-                (3, 8, 8).into(),
-                (4, 16, 16).into(),
-                (5, 5, 16).into(),
-                (6, 10, 16).into(),
-                (7, 3, 16).into(),
-                (8, 6, 16).into(),
+                (0, 1, 1),
+                (1, 2, 2),
+                (2, 4, 4),
+                (3, 8, 8),
+                (4, 16, 16),
+                (5, 5, 16),
+                (6, 10, 16),
+                (7, 3, 16),
+                (8, 6, 16),
             ],
         );
         itertools::assert_equal(
-            collatz.into_iter(),
+            (&collatz).into_iter().map(summary),
             [
-                (0, 1, 1).into(),
-                (1, 2, 2).into(),
-                (2, 4, 4).into(),
-                (3, 8, 8).into(),
-                (4, 16, 16).into(),
-                (5, 5, 16).into(),
-                (6, 10, 16).into(),
-                (7, 3, 16).into(),
-                (8, 6, 16).into(),
+                (0, 1, 1),
+                (1, 2, 2),
+                (2, 4, 4),
+                (3, 8, 8),
+                (4, 16, 16),
+                (5, 5, 16),
+                (6, 10, 16),
+                (7, 3, 16),
+                (8, 6, 16),
             ],
         );
     }
@@ -613,23 +1643,23 @@ mod test {
     #[test]
     fn generate_multiple_down() {
         let mut collatz = Collatz::default();
-        collatz.generate_down(6);
-        collatz.generate_down(80);
+        collatz.generate_down(6).unwrap();
+        collatz.generate_down(80).unwrap();
         itertools::assert_equal(
-            collatz.into_iter(),
+            (&collatz).into_iter().map(summary),
             [
-                (0, 1, 1).into(),
-                (1, 2, 2).into(),
-                (2, 4, 4).into(),
-                (3, 8, 8).into(),
-                (4, 16, 16).into(),
-                (5, 5, 16).into(),
-                (6, 10, 16).into(),
-                (7, 3, 16).into(),
-                (7, 20, 20).into(),
-                (8, 6, 16).into(),
-                (8, 40, 40).into(),
-                (9, 80, 80).into(),
+                (0, 1, 1),
+                (1, 2, 2),
+                (2, 4, 4),
+                (3, 8, 8),
+                (4, 16, 16),
+                (5, 5, 16),
+                (6, 10, 16),
+                (7, 3, 16),
+                (7, 20, 20),
+                (8, 6, 16),
+                (8, 40, 40),
+                (9, 80, 80),
             ],
         );
     }
@@ -637,32 +1667,32 @@ mod test {
     #[test]
     fn generate_fill_down() {
         let mut collatz = Collatz::default();
-        collatz.generate_fill_down(10);
+        collatz.generate_fill_down(10).unwrap();
         itertools::assert_equal(
-            collatz.into_iter(),
+            (&collatz).into_iter().map(summary),
             [
-                (0, 1, 1).into(),
-                (1, 2, 2).into(),
-                (2, 4, 4).into(),
-                (3, 8, 8).into(),
-                (4, 16, 16).into(),
-                (5, 5, 16).into(),
-                (6, 10, 16).into(),
-                (7, 20, 20).into(),
-                (7, 3, 16).into(),
-                (8, 40, 40).into(),
-                (8, 6, 16).into(),
-                (9, 13, 40).into(),
-                (10, 26, 40).into(),
-                (11, 52, 52).into(),
-                (12, 17, 52).into(),
-                (13, 34, 52).into(),
-                (14, 11, 52).into(),
-                (15, 22, 52).into(),
-                (16, 7, 52).into(),
-                (17, 14, 52).into(),
-                (18, 28, 52).into(),
-                (19, 9, 52).into(),
+                (0, 1, 1),
+                (1, 2, 2),
+                (2, 4, 4),
+                (3, 8, 8),
+                (4, 16, 16),
+                (5, 5, 16),
+                (6, 10, 16),
+                (7, 20, 20),
+                (7, 3, 16),
+                (8, 40, 40),
+                (8, 6, 16),
+                (9, 13, 40),
+                (10, 26, 40),
+                (11, 52, 52),
+                (12, 17, 52),
+                (13, 34, 52),
+                (14, 11, 52),
+                (15, 22, 52),
+                (16, 7, 52),
+                (17, 14, 52),
+                (18, 28, 52),
+                (19, 9, 52),
             ],
         );
     }
@@ -670,69 +1700,973 @@ mod test {
     #[test]
     fn generate_single_up() {
         let mut collatz = Collatz::default();
-        collatz.generate_up(32);
+        collatz.generate_up(32, None).unwrap();
         itertools::assert_equal(
-            collatz.into_iter(),
+            (&collatz).into_iter().map(summary),
             [
-                (0, 1, 1).into(),
-                (1, 2, 2).into(),
-                (2, 4, 4).into(),
-                (3, 8, 8).into(),
-                (4, 16, 16).into(),
-                (5, 32, 32).into(),
-                (5, 5, 16).into(),
-                (6, 10, 16).into(),
-                (7, 20, 20).into(),
-                (7, 3, 16).into(),
-                (8, 6, 16).into(),
-                (9, 12, 16).into(),
-                (10, 24, 24).into(),
+                (0, 1, 1),
+                (1, 2, 2),
+                (2, 4, 4),
+                (3, 8, 8),
+                (4, 16, 16),
+                (5, 32, 32),
+                (5, 5, 16),
+                (6, 10, 16),
+                (7, 20, 20),
+                (7, 3, 16),
+                (8, 6, 16),
+                (9, 12, 16),
+                (10, 24, 24),
             ],
         );
+
+        let mut collatz = Collatz::default();
+        collatz.generate_up(32, Some(5)).unwrap();
+        itertools::assert_equal(
+            collatz.iter().map(|node| node.value),
+            [1, 2, 4, 8, 16, 32, 5],
+        );
+
+        // Going up without prior nodes reaches exactly the orbits that stay below `max`
+        for kind in [
+            CollatzKind::Full,
+            CollatzKind::Short,
+            CollatzKind::Odd,
+            CollatzKind::Compact,
+        ] {
+            let mut up = Collatz::new(kind);
+            let mut down = Collatz::new(kind);
+            up.generate_up(1000, None).unwrap();
+            down.generate_fill_down(1000).unwrap();
+            let mut up = up.iter().cloned().collect::<Vec<_>>();
+            let mut down = down
+                .iter()
+                .filter(|node| node.highest_point <= 1000)
+                .cloned()
+                .collect::<Vec<_>>();
+            up.sort_by_key(|node| node.value);
+            down.sort_by_key(|node| node.value);
+            assert_eq!(up, down, "{:?}", kind);
+        }
     }
 
     #[test]
     fn generate_mixed() {
         let mut collatz = Collatz::default();
-        collatz.generate_down(80);
-        collatz.generate_up(16);
+        collatz.generate_down(80).unwrap();
+        collatz.generate_up(16, None).unwrap();
         itertools::assert_equal(
-            collatz.into_iter(),
+            (&collatz).into_iter().map(summary),
             [
-                (0, 1, 1).into(),
-                (1, 2, 2).into(),
-                (2, 4, 4).into(),
-                (3, 8, 8).into(),
-                (4, 16, 16).into(),
-                (5, 5, 16).into(),
-                (6, 10, 16).into(),
-                (7, 20, 20).into(),
-                (7, 3, 16).into(),
-                (8, 40, 40).into(),
-                (8, 6, 16).into(),
-                (9, 80, 80).into(),
-                (9, 12, 16).into(),
+                (0, 1, 1),
+                (1, 2, 2),
+                (2, 4, 4),
+                (3, 8, 8),
+                (4, 16, 16),
+                (5, 5, 16),
+                (6, 10, 16),
+                (7, 20, 20),
+                (7, 3, 16),
+                (8, 40, 40),
+                (8, 6, 16),
+                (9, 80, 80),
+                (9, 13, 40),
+                (9, 12, 16),
             ],
         );
+
+        // Generating up again, or further down, merges with the existing nodes
+        collatz.generate_up(16, None).unwrap();
+        assert_eq!(collatz.len(), 14);
+        collatz.generate_down(26).unwrap();
+        collatz.generate_up(30, None).unwrap();
+        assert_eq!(collatz.get_depth(26), Some(10));
+        assert_eq!(collatz.get_depth(24), Some(10));
+        assert_eq!(collatz.len(), 16);
+        assert_eq!(collatz.iter().filter(|node| node.value == 13).count(), 1);
+    }
+
+    #[test]
+    fn generate_cycles() {
+        let mut collatz = Collatz::<u64>::with_map(AffineMap::new(5, 1, 2, false).unwrap());
+        assert!(collatz.cycles().is_empty());
+        collatz.generate_down(26).unwrap();
+        collatz.generate_down(1).unwrap();
+        collatz.generate_down(17).unwrap();
+        collatz.generate_down(5).unwrap();
+        assert_eq!(
+            collatz.cycles(),
+            [
+                vec![13, 66, 33, 166, 83, 416, 208, 104, 52, 26],
+                vec![1, 6, 3, 16, 8, 4, 2],
+                vec![17, 86, 43, 216, 108, 54, 27, 136, 68, 34],
+            ]
+        );
+        assert_eq!(collatz.cycle_of(&5), Some(0));
+        assert_eq!(collatz.cycle_of(&3), Some(1));
+        assert_eq!(collatz.get_depth(13), Some(0));
+        assert_eq!(collatz.get_depth(26), Some(1));
+        assert_eq!(collatz.get_depth(66), Some(9));
+        assert_eq!(collatz.get_depth(5), Some(2));
+        assert_eq!(collatz.find_common_ancestor(5, 104), Some(26));
+
+        let mut collatz = Collatz::new(CollatzKind::Odd);
+        assert_eq!(collatz.cycles(), [vec![1]]);
+        collatz.generate_fill_down(99).unwrap();
+        assert_eq!(collatz.cycles().len(), 1);
+    }
+
+    #[test]
+    fn generate_cycle_matches_full() {
+        let mut full = Collatz::new(CollatzKind::Full);
+        let mut affine = Collatz::new(CollatzKind::Affine);
+        full.generate_fill_down(100).unwrap();
+        affine.generate_fill_down(100).unwrap();
+        assert_eq!(affine.cycles(), [vec![1, 4, 2]]);
+        itertools::assert_equal(&full, &affine);
+    }
+
+    #[test]
+    fn negative_cycles() {
+        for (kind, cycle) in [
+            (CollatzKind::Full, vec![-5, -14, -7, -20, -10]),
+            (CollatzKind::Short, vec![-5, -7, -10]),
+            (CollatzKind::Odd, vec![-5, -7]),
+            (CollatzKind::Compact, vec![-5, -7]),
+            (CollatzKind::Affine, vec![-5, -14, -7, -20, -10]),
+        ] {
+            let mut collatz = Collatz::<i64>::with_kind(kind);
+            for n in (-200..0).rev() {
+                if collatz.in_domain(&n) {
+                    collatz.generate_down(n).unwrap();
+                }
+            }
+            let roots = collatz.cycles().iter().map(|c| c[0]).collect::<Vec<_>>();
+            let skip = if kind == CollatzKind::Affine { 0 } else { 1 };
+            assert_eq!(roots[skip..], [-1, -5, -17], "{:?}", kind);
+            assert_eq!(collatz.cycles()[skip + 1], cycle, "{:?}", kind);
+            assert_eq!(collatz.get_depth(-5), Some(0));
+            assert_eq!(collatz.cycle_of(&-17), Some(skip + 2));
+
+            // Every predecessor maps back
+            for node in &collatz {
+                assert!(node.highest_point.unsigned_abs() >= node.value.unsigned_abs());
+                let (up1, up2) = collatz.up(node.value);
+                for up in std::iter::once(up1).chain(up2) {
+                    if collatz.in_domain(&up) {
+                        assert_eq!(collatz.down(up), node.value, "{:?}", kind);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn clone_and_send() {
+        let mut collatz = Collatz::default();
+        collatz.generate_fill_down(100).unwrap();
+        let copy = collatz.clone();
+        collatz.generate_fill_down(1000).unwrap();
+
+        let copy = std::thread::spawn(move || copy).join().unwrap();
+        assert!(copy.len() < collatz.len());
+        assert!(!copy.contains(&1000));
+        itertools::assert_equal(
+            copy.iter_orbit(27).unwrap(),
+            collatz.iter_orbit(27).unwrap(),
+        );
+    }
+
+    #[test]
+    fn prune() {
+        let sorted = |collatz: &Collatz| {
+            let mut nodes = collatz.iter().cloned().collect::<Vec<_>>();
+            nodes.sort_by_key(|node| node.value);
+            nodes
+        };
+        let mut small = Collatz::default();
+        small.generate_fill_down(100).unwrap();
+        let mut collatz = Collatz::default();
+        collatz.generate_fill_down(1000).unwrap();
+
+        assert!(collatz.prune(1..=100) > 0);
+        assert_eq!(sorted(&collatz), sorted(&small));
+        collatz.generate_fill_down(1000).unwrap();
+        assert_eq!(collatz.get_depth(871), Some(178));
+
+        assert!(collatz.prune(0..=0) > 0);
+        assert_eq!(collatz.len(), 1);
+        assert!(!collatz.contains(&2));
+    }
+
+    #[test]
+    fn memory_budget() {
+        let mut collatz = Collatz::default();
+        collatz.set_memory_budget(Some(50 * Collatz::<u64>::node_size()));
+        collatz.generate_fill_down(10).unwrap();
+        let len = collatz.len();
+        assert_eq!(
+            collatz.generate_down(27),
+            Err(CollatzError::MemoryBudget {
+                budget: 50 * Collatz::<u64>::node_size()
+            })
+        );
+        assert_eq!(collatz.len(), len);
+        assert!(collatz.generate_fill_down(100).is_err());
+        assert!(collatz.len() <= 50);
+
+        collatz.set_memory_budget(None);
+        collatz.generate_fill_down(100).unwrap();
+        assert!(collatz.memory_usage() >= collatz.len() * Collatz::<u64>::node_size());
     }
 
     #[test]
     fn common_ancestor() {
         let mut collatz = Collatz::default();
-        collatz.generate_down(22);
-        collatz.generate_down(69);
-        collatz.generate_down(70);
-        assert_eq!(collatz.find_common_ancestor(69, 70), 40);
-        assert_eq!(collatz.find_common_ancestor(22, 69), 52);
-        assert_eq!(collatz.find_common_ancestor(69, 69), 69);
+        collatz.generate_down(22).unwrap();
+        collatz.generate_down(69).unwrap();
+        collatz.generate_down(70).unwrap();
+        assert_eq!(collatz.find_common_ancestor(69, 70), Some(40));
+        assert_eq!(collatz.find_common_ancestor(22, 69), Some(52));
+        assert_eq!(collatz.find_common_ancestor(69, 69), Some(69));
+
+        collatz.index_ancestors();
+        assert_eq!(collatz.find_common_ancestor(69, 70), Some(40));
+        assert_eq!(collatz.find_common_ancestor(22, 69), Some(52));
+        assert_eq!(collatz.find_common_ancestor(69, 69), Some(69));
+        assert_eq!(collatz.find_common_ancestor(1, 69), Some(1));
+        collatz.generate_down(27).unwrap();
+        assert!(!collatz.has_ancestor_index());
+
+        // The index agrees with walking down, across several trees
+        let mut collatz = Collatz::<i64>::with_kind(CollatzKind::Short);
+        for n in -60..=60 {
+            collatz.generate_down(n).unwrap();
+        }
+        let mut indexed = collatz.clone();
+        indexed.index_ancestors();
+        assert!(indexed.memory_usage() > collatz.memory_usage());
+        for (a, b) in itertools::iproduct!(-60..=60, -60..=60) {
+            assert_eq!(
+                indexed.find_common_ancestor(a, b),
+                collatz.find_common_ancestor(a, b),
+                "{} {}",
+                a,
+                b
+            );
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let mut collatz = Collatz::default();
+        assert_eq!(collatz.find_merge(69, 70), None);
+        let merge = collatz.merge(69, 70).unwrap().unwrap();
+        assert_eq!(merge.value, 40);
+        assert_eq!(merge.steps, vec![6, 6]);
+        assert_eq!(merge.distance, 12);
+        assert_eq!(collatz.find_merge(69, 69).unwrap().distance, 0);
+
+        // 22 joins the orbit of 69 at 52, so only its first four edges are new
+        let merge = collatz.merge_all(&[69, 70, 22]).unwrap().unwrap();
+        assert_eq!(merge.value, 40);
+        assert_eq!(merge.steps, vec![6, 6, 7]);
+        assert_eq!(merge.distance, 16);
+        assert_eq!(collatz.find_merge_all(&[]), None);
+
+        let merge = collatz.merge(27, 97).unwrap().unwrap();
+        assert_eq!(collatz.find_common_ancestor(27, 97), Some(merge.value));
+        assert_eq!(merge.distance, merge.steps.iter().sum());
+
+        let mut collatz = Collatz::<i64>::with_kind(CollatzKind::Odd);
+        assert_eq!(collatz.merge(3, -3), Ok(None));
+        collatz.index_ancestors();
+        assert_eq!(collatz.find_merge(3, 3).unwrap().steps, vec![0, 0]);
+    }
+
+    #[test]
+    fn predecessors() {
+        // Counted by following the orbit of every value in the range
+        let count = |kind, n: i64, range: RangeInclusive<i64>| {
+            let collatz = Collatz::<i64>::with_kind(kind);
+            range
+                .filter(|&m| m != n && collatz.in_domain(&m))
+                .filter(|&m| itertools::iterate(m, |&m| collatz.down(m)).take(400).any(|m| m == n))
+                .count()
+        };
+
+        let mut collatz = Collatz::<i64>::with_kind(CollatzKind::Full);
+        assert_eq!(collatz.count_predecessors(1, &10), Ok(9));
+        assert_eq!(collatz.count_predecessors(2, &10), Ok(9));
+        for n in 1..=60 {
+            let expected = count(CollatzKind::Full, n, 1..=100);
+            assert_eq!(collatz.count_predecessors(n, &100), Ok(expected), "{}", n);
+        }
+        let mut collatz = Collatz::<i64>::with_kind(CollatzKind::Short);
+        for n in -30..=-1 {
+            let expected = count(CollatzKind::Short, n, -50..=-1);
+            assert_eq!(collatz.count_predecessors(n, &-50), Ok(expected), "{}", n);
+        }
+        let mut collatz = Collatz::<i64>::with_kind(CollatzKind::Odd);
+        assert_eq!(collatz.count_predecessors(2, &100), Err(CollatzError::NotInDomain(2)));
+
+        let mut collatz = Collatz::default();
+        assert_eq!(collatz.count_bounded_predecessors(1, &10), 3);
+        assert_eq!(collatz.count_bounded_predecessors(2, &10), 2);
+        assert_eq!(collatz.count_bounded_predecessors(3, &100), 5);
+        let sizes = (0..8).map(|depth| collatz.subtree_size(1, depth));
+        itertools::assert_equal(sizes, vec![1, 2, 3, 4, 5, 7, 9, 13]);
+        assert_eq!(collatz.subtree_size(3, 0), 1);
+
+        // Counting from the rules agrees with the tree `generate_up` fills in
+        let counts = (1..=100)
+            .map(|n| collatz.count_bounded_predecessors(n, &100))
+            .collect::<Vec<_>>();
+        collatz.generate_up(200, None).unwrap();
+        for n in 1..=100 {
+            assert_eq!(collatz.count_bounded_predecessors(n, &100), counts[n as usize - 1]);
+        }
+        let mut collatz = Collatz::default();
+        collatz.generate_up(u64::MAX, Some(12)).unwrap();
+        assert_eq!(collatz.subtree_size(1, 12), collatz.len());
+
+        // Cycles that are not generated yet are not walked around
+        let collatz = Collatz::<i64>::with_kind(CollatzKind::Short);
+        assert_eq!(collatz.count_bounded_predecessors(-5, &-20), 4);
+        assert_eq!(collatz.subtree_size(-5, 3), 6);
+        let collatz = Collatz::<i64>::with_kind(CollatzKind::Odd);
+        assert_eq!(collatz.count_bounded_predecessors(2, &100), 0);
+        assert_eq!(collatz.subtree_size(2, 3), 0);
+    }
+
+    #[test]
+    fn depth_layers() {
+        let mut collatz = Collatz::default();
+        collatz.generate_fill_down(1000).unwrap();
+        let at_depth = |collatz: &Collatz, depth| {
+            let mut values = collatz
+                .values_at_depth(depth)
+                .filter(|&&n| n <= 1000)
+                .copied()
+                .collect::<Vec<_>>();
+            values.sort_unstable();
+            values
+        };
+        let expected = (1..=1000)
+            .filter(|&n| collatz.stream_orbit(n).unwrap().count() == 112)
+            .collect::<Vec<_>>();
+        assert_eq!(at_depth(&collatz, 111), expected);
+        assert!(expected.contains(&27));
+        let counts = collatz.layer_counts();
+        assert_eq!(counts.iter().sum::<usize>(), collatz.len());
+
+        collatz.index_depths();
+        assert!(collatz.memory_usage() > 0);
+        assert_eq!(at_depth(&collatz, 111), expected);
+        assert_eq!(collatz.layer_counts(), counts);
+        assert_eq!(collatz.values_at_depth(counts.len()).count(), 0);
+        collatz.generate_down(999_999).unwrap();
+        assert!(!collatz.has_depth_index());
+
+        // Whole layers of the inverse tree, as `generate_up` adds them
+        let mut collatz = Collatz::default();
+        assert_eq!(collatz.inverse_layer_counts(1, 7), vec![1, 1, 1, 1, 1, 2, 2, 4]);
+        collatz.generate_up(u64::MAX, Some(30)).unwrap();
+        assert_eq!(collatz.layer_counts(), collatz.inverse_layer_counts(1, 30));
+
+        // Growing by about 4/3 per layer
+        let layers = collatz.inverse_layer_counts(1, 40);
+        let ratio = layers[30..].iter().sum::<usize>() as f64
+            / layers[29..40].iter().sum::<usize>() as f64;
+        assert!((1.2..1.45).contains(&ratio), "{}", ratio);
+        assert!(Collatz::new(CollatzKind::Odd).inverse_layer_counts(2, 5).is_empty());
+
+        // Fast growing layers stop at the first one that is too wide
+        let layers = Collatz::new(CollatzKind::Short).inverse_layer_counts(1, 100_000);
+        assert_eq!(layers.len(), 51);
+        assert!(*layers.last().unwrap() > 1 << 20);
+        assert!(layers[..50].iter().all(|&count| count <= 1 << 20));
+
+        // and before the first one that might not fit the value type
+        let layers = Collatz::new(CollatzKind::Compact).inverse_layer_counts(1, 100_000);
+        assert_eq!(layers.len(), 21);
+        assert_eq!(*layers.last().unwrap(), 1 << 19);
+    }
+
+    #[test]
+    fn orbit_stats() {
+        let mut collatz = Collatz::default();
+        collatz.generate_down(27).unwrap();
+        let data = collatz.get_data(&27).unwrap();
+        assert_eq!(data.depth, 111);
+        assert_eq!(data.highest_point, 9232);
+        assert_eq!(data.peak_step, 77);
+        assert_eq!(data.odd_steps, 41);
+        assert_eq!(collatz.get_stopping_time(27), Some(96));
+        assert_eq!(collatz.get_stopping_time(1), None);
+        assert_eq!(collatz.get_stopping_time(2), Some(1));
+        assert_eq!(collatz.get_peak_step(9232), Some(0));
+
+        let mut collatz = Collatz::new(CollatzKind::Short);
+        collatz.generate_down(27).unwrap();
+        assert_eq!(collatz.get_glide(27), Some(59));
+        assert_eq!(collatz.get_peak_step(27), Some(45));
+        assert_eq!(collatz.get_odd_steps(27), Some(41));
+
+        // Values smaller than their cycle never drop below themselves
+        let mut collatz = Collatz::<u64>::with_map(AffineMap::new(5, 1, 2, false).unwrap());
+        collatz.generate_down(5).unwrap();
+        assert_eq!(collatz.get_stopping_time(5), None);
+        assert_eq!(collatz.get_stopping_time(66), Some(1));
+        assert_eq!(collatz.get_peak_step(5), Some(1));
+    }
+
+    #[test]
+    fn parity_vectors() {
+        let mut full = Collatz::<i64>::with_kind(CollatzKind::Full);
+        let mut short = Collatz::<i64>::with_kind(CollatzKind::Short);
+        let mut odd = Collatz::<i64>::with_kind(CollatzKind::Odd);
+        let mut compact = Collatz::<i64>::with_kind(CollatzKind::Compact);
+        for n in (-199..200).step_by(2) {
+            full.generate_down(n).unwrap();
+            short.generate_down(n).unwrap();
+            odd.generate_down(n).unwrap();
+            let full_parity = full.parity_vector(n).unwrap();
+            let short_parity = short.parity_vector(n).unwrap();
+            let exponents = odd.exponents(n).unwrap();
+
+            assert_eq!(full_parity.full_to_short(), short_parity, "{}", n);
+            assert_eq!(short_parity.short_to_full(), full_parity, "{}", n);
+            assert_eq!(short_parity.short_to_exponents().unwrap(), exponents, "{}", n);
+            assert_eq!(ParityVector::exponents_to_full(&exponents), full_parity, "{}", n);
+            assert_eq!(odd.parity_vector(n).unwrap(), short_parity, "{}", n);
+            assert_eq!(full_parity.count_ones(), full.get_odd_steps(n).unwrap());
+            if n % 3 != 0 {
+                compact.generate_down(n).unwrap();
+                assert_eq!(compact.exponents(n).unwrap(), exponents, "{}", n);
+            }
+        }
+
+        full.generate_down(27).unwrap();
+        let parity = full.parity_vector(27).unwrap();
+        assert_eq!(parity.len(), 111);
+        assert_eq!(parity.as_words().len(), 2);
+        assert_eq!(parity.iter().take(4).collect::<Vec<_>>(), [true, false, true, false]);
+        assert_eq!(odd.exponents(27).unwrap()[..4], [1, 2, 1, 1]);
+        assert_eq!(full.exponents(27), None);
+        assert_eq!(short.parity_vector(1000), None);
+        assert_eq!(full.parity_vector(2).unwrap().short_to_exponents(), None);
+    }
+
+    #[test]
+    fn terras_classes() {
+        let mut short = Collatz::new(CollatzKind::Short);
+        for n in 1..1000u64 {
+            short.generate_down(n).unwrap();
+            let parity = short.parity_vector(n).unwrap();
+            let orbit = short.iter_orbit(n).unwrap().map(|data| data.value);
+            for (k, value) in orbit.chain(Some(1)).enumerate().skip(1) {
+                let class = terras_class(&parity.iter().take(k).collect()).unwrap();
+                assert_eq!(class.modulus, 1 << k);
+                assert_eq!(class.residue, n as u128 % class.modulus);
+                assert_eq!(class.apply(n as u128), Some(value as u128));
+            }
+        }
+
+        // Every residue class has its own parity vector
+        let k = 10;
+        for residue in 0..1 << k {
+            let mut n = residue as u64 + (1 << k);
+            let parity = (0..k)
+                .map(|_| {
+                    let odd = n % 2 == 1;
+                    n = short.down(n);
+                    odd
+                })
+                .collect();
+            assert_eq!(terras_class(&parity).unwrap().residue, residue);
+        }
+
+        // The classes behind the table of Odd in `down`
+        let class = |bits: &[bool]| terras_class(&bits.iter().copied().collect()).unwrap();
+        assert_eq!(class(&[true, true]).residue, 3); // 3 | 7 => (3n + 1) / 2
+        assert_eq!(class(&[true, false, true]).residue, 1); // 1 => (3n + 1) / 4
+        let shortcut = class(&[true, false, false]);
+        assert_eq!((shortcut.residue, shortcut.modulus), (5, 8)); // 5 => shares (3n + 1) / 8
+        // and of Compact, which also splits by the class modulo 3 of the next value
+        assert_eq!(class(&[true, false, false, false, true]).residue, 37 % 32); // 37
+        assert!(terras_class(&ParityVector::exponents_to_short(&[1; 80])).is_some());
+        assert_eq!(terras_class(&ParityVector::exponents_to_short(&[1; 81])), None);
+    }
+
+    #[test]
+    fn jump_table() {
+        let short = Collatz::new(CollatzKind::Short);
+        let table = JumpTable::new(CollatzKind::Short, AffineMap::default(), 8).unwrap();
+        assert_eq!(table.modulus(), 256);
+        for n in 1..1000u64 {
+            let expected = (0..8).fold(n, |n, _| short.down(n));
+            assert_eq!(table.jump(&n), Some((expected, 8)), "{}", n);
+        }
+        let table = JumpTable::new(CollatzKind::Compact, AffineMap::default(), 3).unwrap();
+        assert_eq!(table.modulus(), 384);
+        assert_eq!(JumpTable::new(CollatzKind::Full, AffineMap::default(), 0), None);
+        assert_eq!(JumpTable::new(CollatzKind::Full, AffineMap::default(), 26), None);
+
+        // Jumping ahead finds the same tree as stepping
+        let kinds = [
+            (CollatzKind::Full, AffineMap::default()),
+            (CollatzKind::Short, AffineMap::default()),
+            (CollatzKind::Odd, AffineMap::default()),
+            (CollatzKind::Compact, AffineMap::default()),
+            (CollatzKind::Affine, AffineMap::new(3, 1, 2, true).unwrap()),
+        ];
+        for &(kind, map) in &kinds {
+            let mut stepped = Collatz::<i64>::with_kind_and_map(kind, map);
+            let starts: Vec<i64> = (-999..1000).filter(|n| stepped.in_domain(n)).collect();
+            for &n in &starts {
+                stepped.generate_down(n).unwrap();
+            }
+            for &k in &[1, 4, 10] {
+                let mut jumped = Collatz::<i64>::with_kind_and_map(kind, map);
+                jumped.set_jump_table(Some(k)).unwrap();
+                for &n in &starts {
+                    jumped.generate_down(n).unwrap();
+                    assert_eq!(jumped.get_depth(n), stepped.get_depth(n), "{:?} {}", kind, n);
+                }
+                assert_eq!(jumped.len(), stepped.len(), "{:?}", kind);
+                assert_eq!(jumped.cycles(), stepped.cycles(), "{:?}", kind);
+                assert!(jumped.iter().eq(stepped.iter()), "{:?}", kind);
+            }
+        }
+
+        let mut collatz = Collatz::default();
+        collatz.set_jump_table(Some(8)).unwrap();
+        assert_eq!(collatz.set_jump_table(Some(0)), Err(CollatzError::InvalidJumpTable { k: 0 }));
+        assert_eq!(collatz.jump_table().unwrap().modulus(), 256);
+        collatz.set_jump_table(None).unwrap();
+        assert!(collatz.jump_table().is_none());
+    }
+
+    #[test]
+    fn sieve() {
+        // Residue classes left to check modulo 2^k, as in OEIS A076227
+        let survivors = |k| {
+            let sieve = Sieve::new(CollatzKind::Short, AffineMap::default(), k).unwrap();
+            sieve.modulus() as usize - sieve.descending()
+        };
+        assert_eq!(
+            (1..=10).map(survivors).collect::<Vec<_>>(),
+            [1, 1, 2, 3, 4, 8, 13, 19, 38, 64]
+        );
+
+        let kinds = [
+            CollatzKind::Full,
+            CollatzKind::Short,
+            CollatzKind::Odd,
+            CollatzKind::Compact,
+        ];
+        for &kind in &kinds {
+            let mut collatz = Collatz::new(kind);
+            let report = collatz.generate_fill_sieve(10000, 10).unwrap();
+            let in_domain = (2..=10000).filter(|n| collatz.in_domain(n)).count();
+            assert_eq!(report.checked + report.skipped, in_domain, "{:?}", kind);
+            assert!(report.skipped > report.checked, "{:?}", kind);
+            assert_eq!(*collatz.verified_end(), 10001);
+            assert_eq!(collatz.cycles().len(), 1);
+            assert!(!collatz.contains(&27));
+
+            // Picks up where it stopped
+            let report = collatz.generate_fill_sieve(20000, 10).unwrap();
+            let in_domain = (10001..=20000).filter(|n| collatz.in_domain(n)).count();
+            assert_eq!(report.checked + report.skipped, in_domain, "{:?}", kind);
+        }
+
+        // New cycles are added to the tree, and an orbit that overflows stops the check
+        let mut collatz = Collatz::<u64>::with_map(AffineMap::new(5, 1, 2, false).unwrap());
+        assert!(matches!(
+            collatz.generate_fill_sieve(100, 4),
+            Err(CollatzError::Overflow { start: 7, .. })
+        ));
+        assert_eq!(*collatz.verified_end(), 7);
+        assert_eq!(collatz.cycles().len(), 2);
+        assert!(collatz.contains(&13));
+
+        let mut collatz = Collatz::default();
+        assert_eq!(collatz.generate_fill_sieve(100, 0), Err(CollatzError::InvalidSieve { k: 0 }));
+        assert_eq!(collatz.generate_fill_sieve(100, 30), Err(CollatzError::InvalidSieve { k: 30 }));
+        assert_eq!(*collatz.verified_end(), 2);
+    }
+
+    #[test]
+    fn depth_table() {
+        let kinds = [
+            CollatzKind::Full,
+            CollatzKind::Short,
+            CollatzKind::Odd,
+            CollatzKind::Compact,
+        ];
+        for &kind in &kinds {
+            let mut collatz = Collatz::new(kind);
+            collatz.generate_fill_down(3000).unwrap();
+            let table = DepthTable::new(kind, 3000).unwrap();
+            assert_eq!(table.len(), collatz.len(), "{:?}", kind);
+            assert!(table.memory_usage() < collatz.memory_usage());
+            for data in &collatz {
+                let n = data.value;
+                assert_eq!(table.get_depth(n), Some(data.depth), "{:?} {}", kind, n);
+                assert_eq!(table.get_highest_point(n), Some(data.highest_point));
+                let orbit = collatz.iter_orbit(n).unwrap().map(|data| data.value);
+                assert!(table.iter_orbit(n).unwrap().eq(orbit), "{:?} {}", kind, n);
+            }
+        }
+
+        // Values above max are only there as far as orbits from below reach them
+        let table = DepthTable::new(CollatzKind::Full, 100).unwrap();
+        assert_eq!(table.get_depth(27), Some(111));
+        assert_eq!(table.get_highest_point(27), Some(9232));
+        assert_eq!(table.get_depth(9232), Some(34));
+        assert_eq!(table.get_depth(9233), None);
+        assert_eq!(table.iter_orbit(27).unwrap().count(), 111);
+        assert_eq!(DepthTable::dense_size(CollatzKind::Compact, 95), 32 * 8);
+        assert!(matches!(
+            DepthTable::new(CollatzKind::Affine, 100),
+            Err(CollatzError::UnsupportedKind(CollatzKind::Affine))
+        ));
+        assert!(matches!(
+            DepthTable::new(CollatzKind::Full, 0),
+            Err(CollatzError::InvalidTableSize { max: 0 })
+        ));
+        assert!(matches!(
+            DepthTable::new(CollatzKind::Full, u64::MAX),
+            Err(CollatzError::InvalidTableSize { max: u64::MAX })
+        ));
+        assert!(DepthTable::new(CollatzKind::Compact, 1).is_ok());
+    }
+
+    #[test]
+    fn fill_ranges() {
+        let mut reference = Collatz::new(CollatzKind::Full);
+        reference.generate_fill_down(1199).unwrap();
+
+        let mut collatz = Collatz::new(CollatzKind::Full);
+        collatz.generate_fill(1000..1100).unwrap();
+        assert_eq!(collatz.ranges(), [1..2, 1000..1100]);
+        assert!(collatz.len() < reference.len());
+        assert!(collatz.contains(&1050));
+        assert!(collatz.covers(&(1010..1020)));
+        assert!(!collatz.covers(&(900..1010)));
+        for n in 1000..1100 {
+            assert_eq!(collatz.get_depth(n), reference.get_depth(n), "{}", n);
+        }
+
+        // Touching and overlapping ranges merge
+        collatz.generate_fill(1100..1200).unwrap();
+        assert_eq!(collatz.ranges(), [1..2, 1000..1200]);
+        collatz.generate_fill(950..1050).unwrap();
+        assert_eq!(collatz.ranges(), [1..2, 950..1200]);
+        collatz.generate_fill_down(10).unwrap();
+        assert_eq!(collatz.ranges(), [1..11, 950..1200]);
+        collatz.generate_fill(11..950).unwrap();
+        assert_eq!(collatz.ranges(), &[Range { start: 1, end: 1200 }]);
+        assert_eq!(collatz.len(), reference.len());
+        for data in &reference {
+            assert_eq!(collatz.get_data(&data.value), Some(data));
+        }
+
+        collatz.prune(1000..=1099);
+        assert_eq!(collatz.ranges(), &[Range { start: 1000, end: 1100 }]);
+        assert!(!collatz.covers(&(1..2)));
+
+        // High windows only take the orbits of their values
+        let mut collatz = Collatz::new(CollatzKind::Short);
+        collatz.generate_fill(1_000_000_000..1_000_001_000).unwrap();
+        assert!(collatz.len() < 100_000);
+        assert!(collatz.covers(&(1_000_000_000..1_000_001_000)));
+
+        let mut collatz = Collatz::<i64>::with_kind(CollatzKind::Odd);
+        collatz.generate_fill(-100..-50).unwrap();
+        assert_eq!(collatz.ranges(), [-100..-50, 1..2]);
+        assert_eq!(collatz.cycles().len(), 4);
+    }
+
+    #[test]
+    fn step_fill() {
+        let mut reference = Collatz::<i64>::with_kind(CollatzKind::Short);
+        reference.generate_fill(-500..1000).unwrap();
+
+        let mut collatz = Collatz::<i64>::with_kind(CollatzKind::Short);
+        collatz.generate_fill(200..300).unwrap();
+        let len = collatz.len();
+        let mut progress = FillProgress::new(-500..1000);
+        collatz.step_fill(&mut progress, 700).unwrap();
+        assert_eq!(progress.fraction(), 0.466_666_666_666_666_7);
+        assert!(!collatz.covers(&(300..1000)));
+        // The filled range counts as one start value
+        collatz.step_fill(&mut progress, 101).unwrap();
+        assert_eq!(progress.fraction(), 0.6);
+        while !progress.is_done() {
+            collatz.step_fill(&mut progress, 64).unwrap();
+        }
+        assert_eq!(progress.fraction(), 1.);
+        assert_eq!(progress.nodes(), collatz.len() - len);
+        assert_eq!(collatz.ranges(), reference.ranges());
+        assert_eq!(collatz.len(), reference.len());
+        for data in &reference {
+            assert_eq!(collatz.get_data(&data.value), Some(data));
+        }
+
+        let mut progress = FillProgress::new(u64::MAX - 100..u64::MAX);
+        let mut collatz = Collatz::default();
+        assert!(collatz.step_fill(&mut progress, 10).is_err());
+        assert_eq!(progress.fraction(), 0.);
+        assert_eq!(progress.nodes(), 0);
+        assert_eq!(collatz.len(), Collatz::<u64>::default().len());
+    }
+
+    /// The hand-derived steps of `Odd` and `Compact`, modulo 8 and 96 down and 12 and 18 up.
+    fn hand_derived(kind: CollatzKind, n: i64) -> (i64, (i64, Option<i64>)) {
+        let up = |shortcut: i64, syracuse: Option<i64>| {
+            let syracuse = syracuse.map(|k| (n * k - 1) / 3);
+            (n * shortcut + shortcut / 3, syracuse)
+        };
+        match kind {
+            CollatzKind::Odd => {
+                let down = match n.rem_euclid(8) {
+                    5 => n.div_euclid(4),
+                    3 | 7 => (3 * n + 1) / 2,
+                    _ => (3 * n + 1) / 4,
+                };
+                let syracuse = match n.rem_euclid(12) {
+                    1 | 7 => Some(4),
+                    5 | 11 => Some(2),
+                    _ => None,
+                };
+                (down, up(4, syracuse))
+            }
+            _ => {
+                let down = match n.rem_euclid(96) {
+                    5 | 29 | 53 | 77 => n.div_euclid(4),
+                    85 => n.div_euclid(16),
+                    13 | 61 => (3 * n + 1) / 8,
+                    37 => (3 * n + 1) / 16,
+                    r if r % 8 == 1 => (3 * n + 1) / 4,
+                    _ => (3 * n + 1) / 2,
+                };
+                let (shortcut, syracuse) = match n.rem_euclid(18) {
+                    11 | 17 => (16, 2),
+                    1 | 13 => (4, 4),
+                    5 => (16, 8),
+                    _ => (4, 16),
+                };
+                (down, up(shortcut, Some(syracuse)))
+            }
+        }
+    }
+
+    #[test]
+    fn derived_rules() {
+        let odd = Collatz::<i64>::with_kind(CollatzKind::Odd);
+        let compact = Collatz::<i64>::with_kind(CollatzKind::Compact);
+        let check = |rules: &Rules, collatz: &Collatz<i64>| {
+            for n in (-999..1000).filter(|n| collatz.in_domain(n)) {
+                assert!(rules.in_domain(&n), "{}", n);
+                assert_eq!(rules.down(&n), collatz.checked_down(&n), "{}", n);
+                assert_eq!(rules.up(&n), collatz.checked_up(&n), "{}", n);
+                assert_eq!(rules.is_odd_step(&n), collatz.is_odd_step(&n), "{}", n);
+            }
+        };
+
+        // The derived steps the kinds use are the hand-derived ones
+        for collatz in &[&odd, &compact] {
+            for n in (-999..1000).filter(|n| collatz.in_domain(n)) {
+                let (down, (up1, up2)) = hand_derived(collatz.kind(), n);
+                assert_eq!(collatz.checked_down(&n), Some(down), "{:?} {}", collatz.kind(), n);
+                assert_eq!(collatz.checked_up(&n), (Some(up1), up2), "{:?} {}", collatz.kind(), n);
+            }
+        }
+
+        let rules = derive_rules(3, 0).unwrap();
+        assert_eq!(rules.modulus(), 8);
+        check(&rules, &odd);
+        check(&derive_rules(6, 0).unwrap(), &odd);
+
+        let rules = derive_rules(5, 1).unwrap();
+        assert_eq!((rules.modulus(), rules.up_modulus()), (96, 18));
+        check(&rules, &compact);
+        assert_eq!(rules.down_rule(&85u64), Some(rules::DownRule::Shortcut { divisor: 16 }));
+        assert_eq!(rules.down_rule(&37u64), Some(rules::DownRule::Syracuse { divisor: 16 }));
+        assert_eq!(rules.down_rule(&9u64), None);
+
+        // Deeper tables give the same map, and do not compress it any further
+        for &(a, b) in &[(7, 1), (9, 1), (5, 2)] {
+            let rules = derive_rules(a, b).unwrap();
+            check(&rules, &compact);
+            assert!(!rules.in_domain(&9u64));
+        }
+        assert_eq!(derive_rules(9, 1).unwrap().modulus(), 1536);
+
+        // Too small to tell the chains of shortcuts apart
+        assert_eq!(derive_rules(2, 0), Err(CollatzError::InvalidModulus { a: 2, b: 0 }));
+        assert_eq!(derive_rules(4, 1), Err(CollatzError::InvalidModulus { a: 4, b: 1 }));
+        assert!(derive_rules(64, 0).is_err());
+    }
+
+    #[test]
+    fn lazy_queries() {
+        let mut collatz = Collatz::default();
+        assert!(collatz.iter_orbit(27).is_none());
+        assert_eq!(collatz.find_common_ancestor(69, 70), None);
+        assert_eq!(collatz.depth(27), Ok(111));
+        assert_eq!(collatz.get_depth(27), Some(111));
+        assert_eq!(collatz.common_ancestor(69, 70), Ok(Some(40)));
+        assert_eq!(collatz.orbit(7).unwrap().count(), 16);
+
+        let mut collatz = Collatz::<i64>::with_kind(CollatzKind::Odd);
+        assert_eq!(collatz.common_ancestor(3, -3), Ok(None));
+        assert_eq!(collatz.depth(4), Err(CollatzError::NotInDomain(4)));
+        collatz.generate_fill(1..11).unwrap();
+        assert_eq!(collatz.depth(4), Err(CollatzError::NotInDomain(4)));
+        assert!(collatz.orbit(6).is_err());
     }
 
     #[test]
     fn get_depth() {
         let mut collatz = Collatz::default();
-        collatz.generate_down(69);
-        collatz.generate_down(420);
-        assert_eq!(collatz.get_depth(69), 14);
-        assert_eq!(collatz.get_depth(420), 40);
+        collatz.generate_down(69).unwrap();
+        collatz.generate_down(420).unwrap();
+        assert_eq!(collatz.get_depth(69), Some(14));
+        assert_eq!(collatz.get_depth(420), Some(40));
+        assert_eq!(collatz.get_depth(27), None);
+    }
+
+    #[test]
+    fn overflow_down() {
+        let mut collatz = Collatz::default();
+        assert_eq!(
+            collatz.generate_down(u64::MAX - 1),
+            Err(CollatzError::Overflow {
+                start: u64::MAX - 1,
+                step: 1
+            })
+        );
+        assert_eq!(collatz.len(), 1);
+        assert!(!collatz.contains(&(u64::MAX / 2)));
+        assert_eq!(
+            collatz.generate_fill_down(u64::MAX),
+            Err(CollatzError::Overflow {
+                start: u64::MAX,
+                step: 0
+            })
+        );
+        assert_eq!(collatz.checked_up(&(u64::MAX - 2)), (None, None));
+    }
+
+    #[test]
+    fn stream_orbit() {
+        let collatz = Collatz::default();
+        let mut orbit = collatz.stream_orbit(27).unwrap().with_stats();
+        assert_eq!(orbit.by_ref().count(), 112);
+        let stats = orbit.stats().unwrap();
+        assert_eq!(stats.steps, 111);
+        assert_eq!(stats.odd_steps, 41);
+        assert_eq!(stats.highest_point, 9232);
+        assert_eq!(stats.peak_step, 77);
+        assert_eq!(stats.stopping_time, Some(96));
+        assert!(!orbit.overflowed());
+
+        // The whole cycle, starting anywhere on it
+        itertools::assert_equal(collatz.stream_orbit(1).unwrap(), vec![1, 4, 2]);
+        itertools::assert_equal(collatz.stream_orbit(2).unwrap(), vec![2, 1, 4]);
+        assert!(Collatz::new(CollatzKind::Odd).stream_orbit(2).is_none());
+
+        // A cycle the tree has not found is noticed once the orbit goes around it again
+        let mut collatz = Collatz::<i64>::with_kind(CollatzKind::Short);
+        let orbit = collatz.stream_orbit(-20).unwrap().collect::<Vec<_>>();
+        assert_eq!(orbit[..4], [-20, -10, -5, -7]);
+        assert!(orbit[4..].iter().all(|n| [-5, -7, -10].contains(n)));
+        collatz.generate_down(-20).unwrap();
+        itertools::assert_equal(collatz.stream_orbit(-20).unwrap(), vec![-20, -10, -5, -7]);
+
+        let collatz = Collatz::<u64>::with_map(AffineMap::new(5, 1, 2, false).unwrap());
+        let mut orbit = collatz.stream_orbit(7).unwrap();
+        assert!(orbit.by_ref().last().unwrap() > u64::MAX / 5);
+        assert!(orbit.overflowed());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_fill() {
+        let same_stats = |sequential: &Collatz<i64>, parallel: &Collatz<i64>| {
+            assert_eq!(parallel.len(), sequential.len());
+            for data in sequential {
+                assert_eq!(parallel.get_data(&data.value), Some(data));
+            }
+        };
+        for kind in [CollatzKind::Full, CollatzKind::Odd, CollatzKind::Compact] {
+            let mut sequential = Collatz::<i64>::with_kind(kind);
+            sequential.generate_fill_down(20_000).unwrap();
+            let mut parallel = Collatz::<i64>::with_kind(kind);
+            parallel.par_generate_fill_down(20_000).unwrap();
+            same_stats(&sequential, &parallel);
+            assert_eq!(parallel.ranges(), sequential.ranges());
+        }
+
+        // New cycles, and ranges that are partly filled already
+        let mut sequential = Collatz::<i64>::with_kind(CollatzKind::Short);
+        let mut parallel = sequential.clone();
+        for range in [-300..-1, -10_000..100, 5000..9000] {
+            sequential.generate_fill(range.clone()).unwrap();
+            parallel.par_generate_fill(range).unwrap();
+        }
+        same_stats(&sequential, &parallel);
+        assert_eq!(parallel.cycles().len(), sequential.cycles().len());
+
+        let mut collatz = Collatz::default();
+        assert_eq!(
+            collatz.par_generate_fill(u64::MAX - 9000..u64::MAX),
+            Collatz::default().generate_fill(u64::MAX - 9000..u64::MAX)
+        );
+    }
+
+    #[cfg(feature = "bignum")]
+    #[test]
+    fn bignum_layers() {
+        use num_bigint::BigUint;
+
+        use super::viz::layer_growth::MAX_LAYER_DEPTH;
+
+        // The layers of `i64` stop at the first one that does not fit, rather than come out
+        // short
+        let one = BigUint::from(1u32);
+        let small = Collatz::<i64>::with_kind(CollatzKind::Compact)
+            .inverse_layer_counts(1, MAX_LAYER_DEPTH);
+        let big = Collatz::<BigUint>::with_kind(CollatzKind::Compact)
+            .inverse_layer_counts(one, small.len());
+        assert!(small.len() < MAX_LAYER_DEPTH);
+        assert_eq!(small[..], big[..small.len()]);
+        assert_eq!(big.len(), small.len() + 1);
+    }
+
+    #[cfg(feature = "bignum")]
+    #[test]
+    fn bignum_beyond_u64() {
+        use num_bigint::BigUint;
+
+        let mut collatz = Collatz::<BigUint>::with_kind(CollatzKind::Full);
+        let n = BigUint::from(u64::MAX);
+        collatz.generate_down(n.clone()).unwrap();
+        assert_eq!(collatz.get_depth(n.clone()), Some(863));
+        assert_eq!(
+            collatz.iter_orbit(n).unwrap().next().unwrap().highest_point,
+            "6867367640585024969315698178560".parse().unwrap()
+        );
+
+        // A 40 digit start, without growing the tree
+        let n: BigUint = "1234567890123456789012345678901234567890".parse().unwrap();
+        let mut orbit = collatz.stream_orbit(n.clone()).unwrap().with_stats();
+        assert_eq!(orbit.by_ref().last(), Some(BigUint::from(1u32)));
+        let steps = orbit.stats().unwrap().steps;
+        assert_eq!(collatz.depth(n), Ok(steps));
     }
 }
@@ -0,0 +1,65 @@
+use std::mem::size_of;
+
+use super::{CollatzNode, Node};
+
+/// The ancestors of every node at every power of two steps down, which finds common
+/// ancestors in `O(log depth)` steps instead of `O(depth)` (binary lifting).
+#[derive(Clone)]
+pub(super) struct AncestorIndex {
+    /// `jumps[j][node]` is `2^j` steps down from `node`, or its root if that is closer
+    jumps: Vec<Vec<Node>>,
+}
+
+impl AncestorIndex {
+    pub fn new<T>(arena: &[CollatzNode<T>]) -> Self {
+        let first: Vec<Node> = arena
+            .iter()
+            .enumerate()
+            .map(|(node, data)| data.down.unwrap_or(node as Node))
+            .collect();
+        let max_depth = arena.iter().map(|node| node.data.depth).max().unwrap_or(0);
+        let mut jumps = vec![first];
+        while 1 << jumps.len() <= max_depth {
+            let last = jumps.last().unwrap();
+            let next = last.iter().map(|&node| last[node as usize]).collect();
+            jumps.push(next);
+        }
+        Self { jumps }
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.jumps.iter().map(Vec::capacity).sum::<usize>() * size_of::<Node>()
+    }
+
+    /// `steps` steps down from `node`.
+    fn down(&self, mut node: Node, steps: usize) -> Node {
+        for (j, jumps) in self.jumps.iter().enumerate() {
+            if steps >> j & 1 == 1 {
+                node = jumps[node as usize];
+            }
+        }
+        node
+    }
+
+    /// Same as `Collatz::find_common_ancestor_node`.
+    pub fn common_ancestor<T>(&self, arena: &[CollatzNode<T>], a: Node, b: Node) -> Option<Node> {
+        let (depth_a, depth_b) = (
+            arena[a as usize].data.depth,
+            arena[b as usize].data.depth,
+        );
+        let mut a = self.down(a, depth_a.saturating_sub(depth_b));
+        let mut b = self.down(b, depth_b.saturating_sub(depth_a));
+        if a == b {
+            return Some(a);
+        }
+        // Step both to just above their common ancestor
+        for jumps in self.jumps.iter().rev() {
+            if jumps[a as usize] != jumps[b as usize] {
+                a = jumps[a as usize];
+                b = jumps[b as usize];
+            }
+        }
+        // Both are roots if the trees differ
+        Some(self.jumps[0][a as usize]).filter(|&down| down == self.jumps[0][b as usize])
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::mem::size_of;
+
+use super::{Collatz, CollatzError, CollatzKind};
+
+/// Marks a depth that is not known, or a peak that is in the overflow map.
+const NONE: u32 = u32::MAX;
+
+/// Most values in the domain a table holds densely, at 8 bytes each.
+const MAX_LEN: usize = 1 << 27;
+
+/// Depths and highest points of every value in `1..=max`, without the tree.
+///
+/// Values in the domain are stored densely, two `u32`s each, instead of as tree nodes with
+/// links and a hash map entry. Only the orbit values above `max`, and peaks that do not fit
+/// a `u32`, go in hash maps. The orbits themselves are stepped through again when iterated.
+#[derive(Clone)]
+pub struct DepthTable {
+    steps: Collatz<u64>,
+    max: u64,
+    // Indexed by the position of `n` in the domain, see `index`
+    depths: Vec<u32>,
+    peaks: Vec<u32>,
+    large_peaks: HashMap<u64, u64>,
+    // Depth and highest point of the orbit values above `max`
+    above: HashMap<u64, (u32, u64)>,
+}
+
+impl DepthTable {
+    /// Fills the table with the orbits of every value in the domain of `kind` up to `max`.
+    ///
+    /// Fails with `CollatzError::UnsupportedKind` for `CollatzKind::Affine`, whose cycles are
+    /// not known in advance, and with `CollatzError::InvalidTableSize` if `max` is 0 or the
+    /// table would hold more than 2^27 values.
+    pub fn new(kind: CollatzKind, max: u64) -> Result<Self, CollatzError> {
+        if kind == CollatzKind::Affine {
+            return Err(CollatzError::UnsupportedKind(kind));
+        }
+        let len = index(kind, max)
+            .checked_add(1)
+            .filter(|&len| max >= 1 && len <= MAX_LEN)
+            .ok_or(CollatzError::InvalidTableSize { max })?;
+        let mut table = Self {
+            steps: Collatz::new(kind),
+            max,
+            depths: vec![NONE; len],
+            peaks: vec![NONE; len],
+            large_peaks: HashMap::new(),
+            above: HashMap::new(),
+        };
+        // Every kind but Affine cycles through 1, which is the root
+        table.set(1, 0, 1);
+
+        let mut orbit = Vec::new();
+        for n in 1..=max {
+            if !table.steps.in_domain(&n) || table.get(n).is_some() {
+                continue;
+            }
+            // Step until the orbit reaches a known value, then fill it in backwards
+            let mut m = n;
+            let (mut depth, mut peak) = loop {
+                if let Some(known) = table.get(m) {
+                    break known;
+                }
+                orbit.push(m);
+                m = table.steps.checked_down(&m).ok_or(CollatzError::Overflow {
+                    start: n,
+                    step: orbit.len(),
+                })?;
+            };
+            while let Some(m) = orbit.pop() {
+                depth += 1;
+                peak = peak.max(m);
+                table.set(m, depth, peak);
+            }
+        }
+        Ok(table)
+    }
+
+    pub fn kind(&self) -> CollatzKind {
+        self.steps.kind()
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Number of values whose depth is stored, in `1..=max` or above it.
+    pub fn len(&self) -> usize {
+        self.depths.iter().filter(|&&depth| depth != NONE).count() + self.above.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of bytes the dense part of a table up to `max` takes up.
+    pub fn dense_size(kind: CollatzKind, max: u64) -> usize {
+        (index(kind, max) + 1) * 2 * size_of::<u32>()
+    }
+
+    /// Approximate number of bytes allocated for the table.
+    pub fn memory_usage(&self) -> usize {
+        (self.depths.capacity() + self.peaks.capacity()) * size_of::<u32>()
+            + self.large_peaks.capacity() * (size_of::<(u64, u64)>() + 1)
+            + self.above.capacity() * (size_of::<(u64, (u32, u64))>() + 1)
+    }
+
+    pub fn in_domain(&self, n: u64) -> bool {
+        self.steps.in_domain(&n)
+    }
+
+    pub fn get_depth(&self, n: u64) -> Option<usize> {
+        self.get(n).map(|(depth, _)| depth as usize)
+    }
+
+    /// Value furthest from zero on the orbit of `n`.
+    pub fn get_highest_point(&self, n: u64) -> Option<u64> {
+        self.get(n).map(|(_, peak)| peak)
+    }
+
+    /// Orbit of `n` down to the root, excluding the root, as for [`Collatz::iter_orbit`].
+    pub fn iter_orbit(&self, n: u64) -> Option<DepthOrbit<'_>> {
+        let depth = self.get_depth(n)?;
+        Some(DepthOrbit {
+            steps: &self.steps,
+            value: n,
+            remaining: depth,
+        })
+    }
+
+    fn get(&self, n: u64) -> Option<(u32, u64)> {
+        if n > self.max {
+            return self.above.get(&n).copied();
+        }
+        if !self.steps.in_domain(&n) {
+            return None;
+        }
+        let i = index(self.kind(), n);
+        match (self.depths[i], self.peaks[i]) {
+            (NONE, _) => None,
+            (depth, NONE) => Some((depth, self.large_peaks[&n])),
+            (depth, peak) => Some((depth, peak.into())),
+        }
+    }
+
+    fn set(&mut self, n: u64, depth: u32, peak: u64) {
+        if n > self.max {
+            self.above.insert(n, (depth, peak));
+            return;
+        }
+        let i = index(self.kind(), n);
+        self.depths[i] = depth;
+        self.peaks[i] = match u32::try_from(peak) {
+            Ok(peak) if peak != NONE => peak,
+            _ => {
+                self.large_peaks.insert(n, peak);
+                NONE
+            }
+        };
+    }
+}
+
+/// Position of `n` among the values in the domain of `kind`, which skips the even numbers
+/// for `Odd`, and the multiples of 2 and 3 for `Compact`.
+fn index(kind: CollatzKind, n: u64) -> usize {
+    (match kind {
+        CollatzKind::Odd => n / 2,
+        CollatzKind::Compact => n / 3,
+        _ => n,
+    }) as usize
+}
+
+/// Iterator returned by [`DepthTable::iter_orbit`].
+pub struct DepthOrbit<'a> {
+    steps: &'a Collatz<u64>,
+    value: u64,
+    remaining: usize,
+}
+
+impl<'a> Iterator for DepthOrbit<'a> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let value = self.value;
+        // Every value on the orbit was stepped through without overflowing when filling
+        self.value = self.steps.down(value);
+        Some(value)
+    }
+}
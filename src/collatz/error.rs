@@ -0,0 +1,62 @@
+use std::fmt::{Debug, Display};
+
+use super::{AffineMap, CollatzKind};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CollatzError<T = u64> {
+    /// The orbit of `start` left the range of the value type after `step` steps.
+    Overflow { start: T, step: usize },
+    /// The parameters do not define a map on the positive integers.
+    InvalidMap(AffineMap),
+    /// The map of this kind is not defined on the value.
+    NotInDomain(T),
+    /// Generating more nodes would take the tree past `budget` bytes.
+    MemoryBudget { budget: usize },
+    /// The rules of an accelerated map cannot be derived modulo `2^a * 3^b`.
+    InvalidModulus { a: u32, b: u32 },
+    /// A [`super::Sieve`] with `k` digits is empty or too large to tabulate.
+    InvalidSieve { k: u32 },
+    /// A [`super::JumpTable`] with `k` digits is empty or too large to tabulate.
+    InvalidJumpTable { k: u32 },
+    /// The kind cannot be stored this way, as `CollatzKind::Affine` in a
+    /// [`super::DepthTable`], whose cycles are not known in advance.
+    UnsupportedKind(CollatzKind),
+    /// A [`super::DepthTable`] of every value from 1 up to `max` is empty or too large.
+    InvalidTableSize { max: T },
+}
+
+impl<T: Display> Display for CollatzError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CollatzError::Overflow { start, step } => write!(
+                f,
+                "the orbit of {} overflows at step {}, try a smaller max",
+                start, step
+            ),
+            CollatzError::InvalidMap(map) => write!(f, "{} is not a valid map", map),
+            CollatzError::NotInDomain(n) => write!(f, "the map is not defined on {}", n),
+            CollatzError::MemoryBudget { budget } => write!(
+                f,
+                "the tree would exceed its memory budget of {} bytes, try a smaller max",
+                budget
+            ),
+            CollatzError::InvalidModulus { a, b } => {
+                write!(f, "the rules cannot be derived modulo 2^{} * 3^{}", a, b)
+            }
+            CollatzError::InvalidSieve { k } => {
+                write!(f, "a sieve of {} digits cannot be built", k)
+            }
+            CollatzError::InvalidJumpTable { k } => {
+                write!(f, "a jump table of {} digits cannot be built", k)
+            }
+            CollatzError::InvalidTableSize { max } => {
+                write!(f, "a depth table up to {} cannot be built", max)
+            }
+            CollatzError::UnsupportedKind(kind) => {
+                write!(f, "the {:?} kind is not supported here", kind)
+            }
+        }
+    }
+}
+
+impl<T: Debug + Display> std::error::Error for CollatzError<T> {}
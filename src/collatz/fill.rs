@@ -0,0 +1,90 @@
+use std::ops::Range;
+
+use super::{Collatz, CollatzError, Value};
+
+/// How far a fill of a range has come, advanced with [`Collatz::step_fill`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FillProgress<T = u64> {
+    range: Range<T>,
+    /// Start values from here on up are filled, as the fill goes from the top down
+    next: T,
+    /// Number of nodes added to the tree so far
+    nodes: usize,
+}
+
+impl<T: Value> FillProgress<T> {
+    pub fn new(range: Range<T>) -> Self {
+        Self {
+            next: range.end.clone(),
+            range,
+            nodes: 0,
+        }
+    }
+
+    pub fn range(&self) -> &Range<T> {
+        &self.range
+    }
+
+    pub fn nodes(&self) -> usize {
+        self.nodes
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.next <= self.range.start
+    }
+
+    /// Fraction of the start values of the range that are filled, from 0 to 1.
+    ///
+    /// This is rounded like `f64`, and stays at 0 until the range is done if its ends are
+    /// too far out to tell apart as `f64`.
+    pub fn fraction(&self) -> f64 {
+        if self.is_done() {
+            return 1.;
+        }
+        let end = self.range.end.to_f64();
+        let size = end - self.range.start.to_f64();
+        if size > 0. {
+            ((end - self.next.to_f64()) / size).min(1.)
+        } else {
+            0.
+        }
+    }
+}
+
+impl<T: Value> Collatz<T> {
+    /// Fills at most `k` more start values of the range of `progress`, as
+    /// [`Collatz::generate_fill`] would, so that a large range can be filled a bit at a time.
+    ///
+    /// A filled range that is skipped counts as one start value. The range is recorded as
+    /// filled by the step that finishes it. On an error, `progress` stays at the start value
+    /// that failed.
+    pub fn step_fill(
+        &mut self,
+        progress: &mut FillProgress<T>,
+        k: usize,
+    ) -> Result<(), CollatzError<T>> {
+        let FillProgress { range, next, nodes } = progress;
+        for _ in 0..k {
+            let m = match next.sub_u64(1).filter(|m| *m >= range.start) {
+                Some(m) => m,
+                None => break,
+            };
+            *next = match self.ranges.iter().find(|r| r.contains(&m)) {
+                // Skip to below the filled range
+                Some(filled) => filled.start.clone(),
+                None => {
+                    if self.in_domain(&m) {
+                        let len = self.len();
+                        self.generate_down(m.clone())?;
+                        *nodes += self.len() - len;
+                    }
+                    m
+                }
+            };
+        }
+        if progress.is_done() {
+            self.add_range(progress.range.clone());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+use super::{AffineMap, Collatz, CollatzKind, Value};
+
+/// Largest table [`JumpTable::new`] builds.
+const MAX_MODULUS: u64 = 1 << 24;
+
+/// Several steps of an orbit at once, for the values `modulus * q + r` of one residue class.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Jump {
+    pub steps: u32,
+    /// After `steps` steps the orbit is at `multiplier * q + addend`.
+    pub multiplier: u64,
+    pub addend: u64,
+}
+
+/// Precomputed jumps over the steps that only depend on `n % modulus`.
+///
+/// This is the usual trick of writing `n = 2^k q + r`, so that `k` steps of `Short` take `n`
+/// to `3^a q + d`, where `a` and `d` only depend on `r`. Every kind steps affinely on the
+/// residue classes of a base modulus, 2 for `Full` and `Short`, 8 for `Odd`, 96 for `Compact`
+/// and the divisor for `Affine`. The table uses the base modulus times `k - 1` more factors
+/// of 2, or of the divisor, and jumps as many steps as that determines.
+///
+/// [`Collatz::set_jump_table`] uses one to find where an orbit merges with the tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JumpTable {
+    modulus: u64,
+    /// Indexed by `n % modulus`, `None` outside the domain or where no step is determined
+    jumps: Vec<Option<Jump>>,
+}
+
+impl JumpTable {
+    /// Builds the table for `k` digits of the kind and map, or returns `None` if it would
+    /// have more than 2^24 entries.
+    pub fn new(kind: CollatzKind, map: AffineMap, k: u32) -> Option<Self> {
+        let classes = ClassSteps::new(kind, map, k)?;
+        let jumps = (0..classes.modulus)
+            .map(|r| classes.walk(r, |_| true).filter(|jump| jump.steps > 0))
+            .collect();
+        Some(Self {
+            modulus: classes.modulus,
+            jumps,
+        })
+    }
+
+    pub fn modulus(&self) -> u64 {
+        self.modulus
+    }
+
+    pub fn get<T: Value>(&self, n: &T) -> Option<Jump> {
+        self.jumps[n.rem_u64(self.modulus) as usize]
+    }
+
+    /// Where the orbit of `n` is after the steps of its jump, and how many steps that is.
+    ///
+    /// Returns `None` if `n` has no jump or the value overflows `T`.
+    pub fn jump<T: Value>(&self, n: &T) -> Option<(T, usize)> {
+        let jump = self.get(n)?;
+        let value = n
+            .div_u64(self.modulus)
+            .mul_add(jump.multiplier, jump.addend)?;
+        Some((value, jump.steps as usize))
+    }
+}
+
+/// Steps of a kind on the residue classes modulo `base` times `k - 1` more digits; see
+/// [`JumpTable`].
+pub(super) struct ClassSteps {
+    collatz: Collatz<u64>,
+    base: u64,
+    pub modulus: u64,
+}
+
+impl ClassSteps {
+    pub fn new(kind: CollatzKind, map: AffineMap, k: u32) -> Option<Self> {
+        let (base, digit) = match kind {
+            CollatzKind::Full | CollatzKind::Short => (2, 2),
+            CollatzKind::Odd => (8, 2),
+            CollatzKind::Compact => (96, 2),
+            CollatzKind::Affine => (map.divisor, map.divisor),
+        };
+        let modulus = digit
+            .checked_pow(k.checked_sub(1)?)?
+            .checked_mul(base)
+            .filter(|&m| m <= MAX_MODULUS)?;
+        Some(Self {
+            collatz: Collatz::with_kind_and_map(kind, map),
+            base,
+            modulus,
+        })
+    }
+
+    /// Single step of the class of `n` modulo `base`, with the slope between two lifts.
+    fn step(&self, n: u64) -> Option<(u64, u64)> {
+        let next = self.collatz.checked_down(&n)?;
+        let lifted = self.collatz.checked_down(&n.checked_add(self.base)?)?;
+        Some((next, lifted.checked_sub(next)?))
+    }
+
+    /// Takes the steps of the class of `r` while they are determined, or until `visit`
+    /// returns false on the jump so far. Returns `None` outside the domain.
+    pub fn walk(&self, r: u64, mut visit: impl FnMut(&Jump) -> bool) -> Option<Jump> {
+        if !self.collatz.in_domain(&r) {
+            return None;
+        }
+        // The orbits of the class are at `multiplier * q + addend` so far, which is a single
+        // class modulo `base` as long as `base` divides `multiplier`
+        let mut jump = Jump {
+            steps: 0,
+            multiplier: self.modulus,
+            addend: r,
+        };
+        while jump.multiplier.is_multiple_of(self.base) {
+            let next = self.step(jump.addend).and_then(|(next, slope)| {
+                Some(Jump {
+                    steps: jump.steps + 1,
+                    multiplier: (jump.multiplier / self.base).checked_mul(slope)?,
+                    addend: next,
+                })
+            });
+            match next {
+                Some(next) => jump = next,
+                None => break,
+            }
+            if !visit(&jump) {
+                break;
+            }
+        }
+        Some(jump)
+    }
+}
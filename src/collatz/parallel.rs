@@ -0,0 +1,118 @@
+use std::collections::HashSet;
+use std::ops::Range;
+
+use rayon::prelude::*;
+
+use super::{Collatz, CollatzError, Value};
+
+/// Number of start values each worker follows the orbits of at a time.
+const CHUNK: u64 = 1 << 12;
+
+/// Part of an orbit that is not in the tree yet, followed by a worker.
+struct Segment<T> {
+    values: Vec<T>,
+    /// The value after the last one, which is in the tree or in an earlier segment of the
+    /// same chunk, or else in `values` if the orbit ran into itself
+    join: T,
+}
+
+impl<T: Value + Send + Sync> Collatz<T> {
+    /// Same as [`Collatz::generate_fill_down`], following the orbits on worker threads.
+    pub fn par_generate_fill_down(&mut self, max: T) -> Result<(), CollatzError<T>> {
+        let end = max.mul_add(1, 1).ok_or(CollatzError::Overflow {
+            start: max.clone(),
+            step: 0,
+        })?;
+        self.par_generate_fill(T::from_u64(1)..end)
+    }
+
+    /// Same as [`Collatz::generate_fill`], following the orbits on worker threads.
+    ///
+    /// The range is split into chunks, and a worker follows the orbit of each start value in
+    /// a chunk until it reaches the tree, or a value it has followed before. The segments of
+    /// orbits are then added to the tree one at a time, in the order `generate_fill` would
+    /// add their orbits, so the depths and orbit stats are the same as with `generate_fill`.
+    pub fn par_generate_fill(&mut self, range: Range<T>) -> Result<(), CollatzError<T>> {
+        // From the top down, as `generate_fill` goes
+        let mut chunks = Vec::new();
+        let mut end = range.end.clone();
+        while end > range.start {
+            let start = end
+                .sub_u64(CHUNK)
+                .filter(|start| *start > range.start)
+                .unwrap_or_else(|| range.start.clone());
+            chunks.push(start.clone()..end);
+            end = start;
+        }
+
+        let this = &*self;
+        let segments = chunks
+            .par_iter()
+            .map(|chunk| this.follow_chunk(chunk))
+            .collect::<Vec<_>>();
+        for segment in segments.into_iter().flatten() {
+            self.add_segment(segment?)?;
+        }
+        self.add_range(range);
+        Ok(())
+    }
+
+    /// Follows the orbits of the start values in `chunk` that are not in the tree, from the
+    /// top down, stopping at the first one that overflows.
+    fn follow_chunk(&self, chunk: &Range<T>) -> Vec<Result<Segment<T>, CollatzError<T>>> {
+        let mut seen = HashSet::new();
+        let mut segments = Vec::new();
+        let mut m = chunk.end.clone();
+        while let Some(start) = m.sub_u64(1).filter(|start| *start >= chunk.start) {
+            m = start.clone();
+            if !self.in_domain(&start) || self.contains(&start) || seen.contains(&start) {
+                continue;
+            }
+            let mut values = Vec::new();
+            let mut n = start.clone();
+            while !self.contains(&n) && seen.insert(n.clone()) {
+                let next = self.checked_down(&n);
+                values.push(n);
+                n = match next {
+                    Some(next) => next,
+                    None => {
+                        let step = values.len() - 1;
+                        segments.push(Err(CollatzError::Overflow { start, step }));
+                        return segments;
+                    }
+                };
+            }
+            segments.push(Ok(Segment { values, join: n }));
+        }
+        segments
+    }
+
+    /// Adds the values of `segment` that are not in the tree yet, from its start on.
+    fn add_segment(&mut self, segment: Segment<T>) -> Result<(), CollatzError<T>> {
+        let Segment { values, join } = segment;
+        let merge_at = values
+            .iter()
+            .position(|n| self.contains(n))
+            .unwrap_or(values.len());
+        let merge_value = values.get(merge_at).unwrap_or(&join);
+        if !self.contains(merge_value) {
+            // The orbit ends in a new cycle, which `generate_down` takes care of
+            return self.generate_down(values[0].clone());
+        }
+        let merge_node = self.get_node(merge_value).unwrap();
+
+        let first_new = self.arena.len();
+        let mut prev_node = None;
+        for n in values.into_iter().take(merge_at) {
+            if let Err(err) = self.check_memory_budget() {
+                self.remove_new_nodes(first_new);
+                return Err(err);
+            }
+            prev_node = Some(self.push_pending(n, prev_node));
+        }
+        if let Some(last) = prev_node {
+            self.link_pending(last, merge_node);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+use std::iter::FromIterator;
+
+/// Parity vector of an orbit, as a bitset.
+///
+/// Bit `i` is set when step `i` of the orbit multiplies, which for the Full and Short kinds
+/// means the value at step `i` is odd.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ParityVector {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl ParityVector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The bits packed into words, starting from the least significant bit of the first.
+    pub fn as_words(&self) -> &[u64] {
+        &self.words
+    }
+
+    pub fn get(&self, i: usize) -> Option<bool> {
+        if i < self.len {
+            Some(self.words[i / 64] >> (i % 64) & 1 == 1)
+        } else {
+            None
+        }
+    }
+
+    pub fn push(&mut self, odd: bool) {
+        if self.len.is_multiple_of(64) {
+            self.words.push(0);
+        }
+        if odd {
+            self.words[self.len / 64] |= 1 << (self.len % 64);
+        }
+        self.len += 1;
+    }
+
+    /// Number of odd steps.
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = bool> + '_ {
+        (0..self.len).map(move |i| self.get(i).unwrap())
+    }
+
+    /// Converts the parity vector of a Full orbit to that of the Short orbit of the same
+    /// value, by dropping the halving that follows every odd step.
+    pub fn full_to_short(&self) -> Self {
+        let mut short = Self::new();
+        let mut bits = self.iter();
+        while let Some(odd) = bits.next() {
+            short.push(odd);
+            if odd {
+                bits.next();
+            }
+        }
+        short
+    }
+
+    /// Converts the parity vector of a Short orbit to that of the Full orbit of the same
+    /// value.
+    pub fn short_to_full(&self) -> Self {
+        let mut full = Self::new();
+        for odd in self.iter() {
+            full.push(odd);
+            if odd {
+                full.push(false);
+            }
+        }
+        full
+    }
+
+    /// Converts the parity vector of a Short orbit to the exponents of the Odd orbit of the
+    /// same value: every odd step followed by `k - 1` even steps becomes `k`.
+    ///
+    /// Returns `None` if the orbit starts at an even value.
+    pub fn short_to_exponents(&self) -> Option<Vec<u32>> {
+        let mut exponents: Vec<u32> = Vec::new();
+        for odd in self.iter() {
+            if odd {
+                exponents.push(1);
+            } else {
+                *exponents.last_mut()? += 1;
+            }
+        }
+        Some(exponents)
+    }
+
+    /// Converts the exponents of an Odd orbit to the parity vector of the Short orbit of
+    /// the same value.
+    pub fn exponents_to_short(exponents: &[u32]) -> Self {
+        let mut short = Self::new();
+        for &k in exponents {
+            short.push(true);
+            for _ in 1..k {
+                short.push(false);
+            }
+        }
+        short
+    }
+
+    /// Same as [`ParityVector::short_to_exponents`], for the parity vector of a Full orbit.
+    pub fn full_to_exponents(&self) -> Option<Vec<u32>> {
+        self.full_to_short().short_to_exponents()
+    }
+
+    /// Same as [`ParityVector::exponents_to_short`], giving the parity vector of the Full
+    /// orbit.
+    pub fn exponents_to_full(exponents: &[u32]) -> Self {
+        Self::exponents_to_short(exponents).short_to_full()
+    }
+}
+
+impl FromIterator<bool> for ParityVector {
+    fn from_iter<I: IntoIterator<Item = bool>>(iter: I) -> Self {
+        let mut parity = Self::new();
+        for odd in iter {
+            parity.push(odd);
+        }
+        parity
+    }
+}
+
+/// The values whose orbits under Short start with a given parity vector of length `k`.
+///
+/// Terras showed that these are exactly one residue class modulo `2^k`, and that after `k`
+/// steps they are all sent to `(multiplier * n + offset) / modulus`, where `multiplier` is 3
+/// to the number of odd steps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TerrasClass {
+    pub residue: u128,
+    /// `2^k`
+    pub modulus: u128,
+    pub multiplier: u128,
+    pub offset: u128,
+}
+
+impl TerrasClass {
+    /// The value `n` is sent to after `k` steps, if `n` is in this class.
+    pub fn apply(&self, n: u128) -> Option<u128> {
+        if n % self.modulus != self.residue {
+            return None;
+        }
+        Some(self.multiplier.checked_mul(n)?.checked_add(self.offset)? / self.modulus)
+    }
+}
+
+/// Inverts the Terras map: finds the residue class whose orbits under Short start with
+/// `parity`, one bit at a time.
+///
+/// Returns `None` if the coefficients overflow `u128`, which takes more than 80 steps.
+pub fn terras_class(parity: &ParityVector) -> Option<TerrasClass> {
+    let mut class = TerrasClass {
+        residue: 0,
+        modulus: 1,
+        multiplier: 1,
+        offset: 0,
+    };
+    // Where the orbit of `residue` is after the steps so far
+    let mut value: u128 = 0;
+    for odd in parity.iter() {
+        if (value % 2 == 1) != odd {
+            // Adding `modulus` to the residue adds the odd `multiplier` to its current value
+            class.residue += class.modulus;
+            value += class.multiplier;
+        }
+        if odd {
+            value = value.checked_mul(3)?.checked_add(1)? / 2;
+            class.offset = class.offset.checked_mul(3)?.checked_add(class.modulus)?;
+            class.multiplier = class.multiplier.checked_mul(3)?;
+        } else {
+            value /= 2;
+        }
+        class.modulus = class.modulus.checked_mul(2)?;
+    }
+    Some(class)
+}
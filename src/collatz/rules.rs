@@ -0,0 +1,226 @@
+use super::{mul_div3, CollatzError, Value};
+
+/// Largest table [`derive_rules`] builds.
+const MAX_MODULUS: u64 = 1 << 24;
+
+/// Lifts of each residue class the derived rules are checked on.
+const LIFTS: u64 = 16;
+
+/// One step of a derived map, on a residue class of its modulus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DownRule {
+    /// `n` is sent to `(n - (divisor - 1) / 3) / divisor`, skipping the shortcut parents that
+    /// are not in the domain.
+    Shortcut { divisor: u64 },
+    /// `n` is sent to `(3n + 1) / divisor`.
+    Syracuse { divisor: u64 },
+}
+
+/// Predecessors of a residue class of the derived map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UpRule {
+    /// `n` is reached from `n * shortcut + (shortcut - 1) / 3`.
+    pub shortcut: u64,
+    /// `n` is reached from `(n * syracuse - 1) / 3`, if any.
+    pub syracuse: Option<u64>,
+}
+
+/// Accelerated Collatz map derived by [`derive_rules`].
+///
+/// The domain is the odd numbers, or the numbers coprime to 6 when the modulus is a multiple
+/// of 3. Values that are not in the domain are skipped: a value of the form `4m + 1` goes to
+/// the first of `m`, `(m - 1) / 4`, ... that is in the domain, if that chain gets there, and
+/// every other value takes a step of the Syracuse map `(3n + 1) / 2^k`.
+///
+/// Modulo 8 this is `CollatzKind::Odd`, and modulo 96 `CollatzKind::Compact`, which step with
+/// these rules. Every larger modulus gives the same map as the smallest one with the same
+/// domain, checked on a larger table. Composing the steps that the extra digits determine
+/// would give a value more than two predecessors, which the tree of a [`super::Collatz`]
+/// cannot hold.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rules {
+    modulus: u64,
+    coprime_to_3: bool,
+    /// Indexed by `n % modulus`, `None` outside the domain
+    down: Vec<Option<DownRule>>,
+    up_modulus: u64,
+    /// Indexed by `n % up_modulus`, `None` outside the domain
+    up: Vec<Option<UpRule>>,
+}
+
+impl Rules {
+    /// `2^a * 3^b`
+    pub fn modulus(&self) -> u64 {
+        self.modulus
+    }
+
+    /// Modulus of the table of predecessors, `2 * 3^(b + 1)`.
+    pub fn up_modulus(&self) -> u64 {
+        self.up_modulus
+    }
+
+    pub fn in_domain<T: Value>(&self, n: &T) -> bool {
+        n.rem_u64(2) != 0 && !(self.coprime_to_3 && n.rem_u64(3) == 0)
+    }
+
+    /// The rule `n` follows, or `None` outside the domain.
+    pub fn down_rule<T: Value>(&self, n: &T) -> Option<DownRule> {
+        self.down[n.rem_u64(self.modulus) as usize]
+    }
+
+    /// Same as [`Rules::down_rule`], for the predecessors of `n`.
+    pub fn up_rule<T: Value>(&self, n: &T) -> Option<UpRule> {
+        self.up[n.rem_u64(self.up_modulus) as usize]
+    }
+
+    /// Next value in the orbit of `n`, or `None` if `n` is not in the domain or the next
+    /// value overflows `T`.
+    pub fn down<T: Value>(&self, n: &T) -> Option<T> {
+        Some(match self.down_rule(n)? {
+            // integer arithmetic for (n - (divisor - 1) / 3) / divisor
+            DownRule::Shortcut { divisor } => n.div_u64(divisor),
+            DownRule::Syracuse { divisor } => n.mul_add(3, 1)?.div_u64(divisor),
+        })
+    }
+
+    /// Predecessors of `n`, where a predecessor that overflows `T` is `None`.
+    ///
+    /// Both are `None` if `n` is not in the domain.
+    pub fn up<T: Value>(&self, n: &T) -> (Option<T>, Option<T>) {
+        match self.up_rule(n) {
+            Some(UpRule { shortcut, syracuse }) => (
+                n.mul_add(shortcut, shortcut / 3),
+                syracuse.and_then(|k| mul_div3(n, k)),
+            ),
+            None => (None, None),
+        }
+    }
+
+    /// Whether the step from `n` multiplies.
+    pub fn is_odd_step<T: Value>(&self, n: &T) -> bool {
+        matches!(self.down_rule(n), Some(DownRule::Syracuse { .. }))
+    }
+
+    /// Checks the tables on the first lifts of every residue class: each rule must agree with
+    /// a direct step, and every value must be one of the predecessors of its next value.
+    fn check(&self) -> bool {
+        let down_ok = (0..self.modulus).all(|r| {
+            (0..LIFTS).map(|t| r + t * self.modulus).all(|n| {
+                match (self.down_rule(&n), self.in_domain(&n)) {
+                    (Some(rule), true) => {
+                        let next = self.down(&n).unwrap();
+                        let (up1, up2) = self.up(&next);
+                        rule == step_rule(n.into(), self.coprime_to_3).0
+                            && self.in_domain(&next)
+                            && (up1 == Some(n) || up2 == Some(n))
+                    }
+                    (None, false) => true,
+                    _ => false,
+                }
+            })
+        });
+        let up_ok = (0..self.up_modulus).all(|r| {
+            (0..LIFTS).map(|t| r + t * self.up_modulus).all(|n| {
+                let (up1, up2) = self.up(&n);
+                up1.iter()
+                    .chain(up2.iter())
+                    .all(|m| self.down(m) == Some(n))
+            })
+        });
+        down_ok && up_ok
+    }
+}
+
+/// Derives the rules of the accelerated map modulo `2^a * 3^b`; see [`Rules`].
+///
+/// `b` only picks the domain: the odd numbers when it is 0, and the numbers coprime to 6
+/// otherwise. A larger `a` or `b` gives a larger table of the same map.
+///
+/// Every rule is checked to depend only on the residue class, and to agree with the
+/// predecessors, before it is returned. Fails with `CollatzError::InvalidModulus` if the
+/// modulus is too small to determine the rules, at least 8 for the odd numbers and 96 for the
+/// numbers coprime to 6, or too large to tabulate.
+pub fn derive_rules(a: u32, b: u32) -> Result<Rules, CollatzError> {
+    let invalid = CollatzError::InvalidModulus { a, b };
+    let modulus = 2u64
+        .checked_pow(a)
+        .zip(3u64.checked_pow(b))
+        .and_then(|(p, q)| p.checked_mul(q))
+        .filter(|&m| m <= MAX_MODULUS)
+        .ok_or_else(|| invalid.clone())?;
+    let coprime_to_3 = b > 0;
+    let in_domain = |n: u128| in_domain(n, coprime_to_3);
+
+    let mut down = vec![None; modulus as usize];
+    for r in (0..modulus).filter(|&r| in_domain(r.into())) {
+        let (rule, bits) = step_rule(r.into(), coprime_to_3);
+        if bits > a {
+            return Err(invalid);
+        }
+        down[r as usize] = Some(rule);
+    }
+
+    let up_modulus = 2 * 3u64.pow(b + 1);
+    let mut up = vec![None; up_modulus as usize];
+    for r in (1..up_modulus).filter(|&r| in_domain(r.into())) {
+        up[r as usize] = Some(up_rule(r.into(), coprime_to_3));
+    }
+
+    let rules = Rules {
+        modulus,
+        coprime_to_3,
+        down,
+        up_modulus,
+        up,
+    };
+    if rules.check() {
+        Ok(rules)
+    } else {
+        Err(invalid)
+    }
+}
+
+fn in_domain(n: u128, coprime_to_3: bool) -> bool {
+    n % 2 == 1 && !(coprime_to_3 && n.is_multiple_of(3))
+}
+
+/// The rule `n` follows, and how many of its low bits decide it.
+fn step_rule(n: u128, coprime_to_3: bool) -> (DownRule, u32) {
+    let in_domain = |n: u128| in_domain(n, coprime_to_3);
+    // Checking `x % 8` after `i` shortcuts takes `n` modulo `2^(2i + 3)`
+    let mut bits = 3;
+    let mut x = n;
+    let mut divisor = 1;
+    while x % 8 == 5 {
+        x /= 4;
+        divisor *= 4;
+        if in_domain(x) {
+            return (DownRule::Shortcut { divisor }, bits);
+        }
+        bits += 2;
+    }
+    // A Syracuse exponent of `k` takes `n` modulo `2^(k + 1)`
+    let k = (3 * n + 1).trailing_zeros();
+    (DownRule::Syracuse { divisor: 1 << k }, bits.max(k + 1))
+}
+
+/// The predecessors of `n`: the first shortcut parent in the domain, and the Syracuse
+/// predecessor with the smallest exponent in the domain, if it steps to `n`.
+fn up_rule(n: u128, coprime_to_3: bool) -> UpRule {
+    let in_domain = |n: u128| in_domain(n, coprime_to_3);
+    let mut shortcut = 4;
+    while !in_domain(n * shortcut + shortcut / 3) {
+        shortcut *= 4;
+    }
+    // Candidates of the right parity are 4m + 1 apart, so one of any three is coprime to 3
+    let syracuse = (1..8)
+        .map(|k| 1 << k)
+        .find(|&k| (n * k) % 3 == 1 && in_domain((n * k - 1) / 3))
+        .filter(|&k| {
+            step_rule((n * k - 1) / 3, coprime_to_3).0 == DownRule::Syracuse { divisor: k as u64 }
+        });
+    UpRule {
+        shortcut: shortcut as u64,
+        syracuse: syracuse.map(|k| k as u64),
+    }
+}
@@ -0,0 +1,64 @@
+use super::jump::ClassSteps;
+use super::{AffineMap, CollatzKind, Value};
+
+/// Outcome of [`Collatz::generate_fill_sieve`](super::Collatz::generate_fill_sieve).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SieveReport {
+    /// Values whose orbits were followed until they dropped below them
+    pub checked: usize,
+    /// Values in residue classes that provably drop below them
+    pub skipped: usize,
+}
+
+/// Residue classes whose orbits provably drop below where they start.
+///
+/// The class of `r` modulo `modulus` takes `n = modulus * q + r` to `multiplier * q + addend`
+/// in the steps it determines, as in a [`JumpTable`](super::JumpTable). Once `multiplier` is
+/// smaller than `modulus`, that is below `n` for every large enough `q`. When values are
+/// checked in increasing order, the orbits of these classes only reach values that are
+/// already checked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sieve {
+    modulus: u64,
+    /// Indexed by `n % modulus`, the smallest `q` from which the class drops below `n`
+    descents: Vec<Option<u64>>,
+}
+
+impl Sieve {
+    /// Builds the sieve for `k` digits of the kind and map, or returns `None` if it would
+    /// have more than 2^24 entries.
+    pub fn new(kind: CollatzKind, map: AffineMap, k: u32) -> Option<Self> {
+        let classes = ClassSteps::new(kind, map, k)?;
+        let modulus = classes.modulus;
+        let descents = (0..modulus)
+            .map(|r| {
+                let jump = classes
+                    .walk(r, |jump| jump.multiplier >= modulus)
+                    .filter(|jump| jump.multiplier < modulus)?;
+                // multiplier * q + addend < modulus * q + r
+                Some(match jump.addend.checked_sub(r) {
+                    Some(above) => above / (modulus - jump.multiplier) + 1,
+                    None => 0,
+                })
+            })
+            .collect();
+        Some(Self { modulus, descents })
+    }
+
+    pub fn modulus(&self) -> u64 {
+        self.modulus
+    }
+
+    /// Number of residue classes that drop below where they start.
+    pub fn descending(&self) -> usize {
+        self.descents.iter().filter(|descent| descent.is_some()).count()
+    }
+
+    /// Whether the orbit of `n` provably drops below `n`.
+    pub fn descends<T: Value>(&self, n: &T) -> bool {
+        match self.descents[n.rem_u64(self.modulus) as usize] {
+            Some(q) => n.div_u64(self.modulus) >= T::from_u64(q),
+            None => false,
+        }
+    }
+}
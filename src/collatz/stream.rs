@@ -0,0 +1,127 @@
+use std::cmp::Ordering;
+
+use super::{Collatz, Value};
+
+/// Stats of the steps a [`StreamOrbit`] has taken so far.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StepStats<T = u64> {
+    pub start: T,
+    /// Number of steps taken, one less than the number of values yielded
+    pub steps: usize,
+    /// Number of steps that multiply, rather than only divide
+    pub odd_steps: usize,
+    /// The value furthest from zero so far
+    pub highest_point: T,
+    /// Number of steps until the orbit reached `highest_point`
+    pub peak_step: usize,
+    /// Number of steps until the orbit first got closer to zero than `start`, if it has
+    pub stopping_time: Option<usize>,
+}
+
+/// Orbit of a value computed one step at a time, without the tree; see
+/// [`Collatz::stream_orbit`].
+///
+/// Every value is yielded, up to and including the cycle the orbit ends in. The orbit ends
+/// right before the first value that repeats when it enters a cycle the tree has found, as
+/// every kind but `Affine` has found the cycle through 1. Otherwise the repeat is spotted
+/// with Brent's algorithm, after going around the cycle at most a few times.
+pub struct StreamOrbit<'a, T> {
+    collatz: &'a Collatz<T>,
+    next: Option<T>,
+    /// First value of a known cycle, which closes the orbit when it comes around again
+    entry: Option<T>,
+    // Brent's cycle detection, as long as no known cycle is entered
+    tortoise: T,
+    power: usize,
+    length: usize,
+    overflowed: bool,
+    stats: Option<StepStats<T>>,
+}
+
+impl<'a, T: Value> StreamOrbit<'a, T> {
+    pub(super) fn new(collatz: &'a Collatz<T>, n: T) -> Self {
+        Self {
+            collatz,
+            tortoise: n.clone(),
+            next: Some(n),
+            entry: None,
+            power: 1,
+            length: 0,
+            overflowed: false,
+            stats: None,
+        }
+    }
+
+    /// Keeps track of [`StepStats`] from the next value on.
+    pub fn with_stats(mut self) -> Self {
+        self.stats = self.next.clone().map(|start| StepStats {
+            highest_point: start.clone(),
+            start,
+            steps: 0,
+            odd_steps: 0,
+            peak_step: 0,
+            stopping_time: None,
+        });
+        self
+    }
+
+    /// The stats of the values yielded so far, if enabled with [`StreamOrbit::with_stats`].
+    pub fn stats(&self) -> Option<&StepStats<T>> {
+        self.stats.as_ref()
+    }
+
+    /// Whether the orbit ended because a value overflowed `T`, rather than in a cycle.
+    pub fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+
+    /// The value after `value`, or `None` if it was yielded already or overflows.
+    fn step(&mut self, value: &T) -> Option<T> {
+        let cycles = self.collatz.cycles();
+        if self.entry.is_none() && cycles.iter().any(|cycle| cycle.contains(value)) {
+            self.entry = Some(value.clone());
+        }
+        let next = self.collatz.checked_down(value);
+        self.overflowed = next.is_none();
+        let next = next?;
+
+        match &self.entry {
+            Some(entry) if *entry == next => return None,
+            Some(_) => (),
+            None if next == self.tortoise => return None,
+            None => {
+                self.length += 1;
+                if self.length == self.power {
+                    self.tortoise = next.clone();
+                    self.power *= 2;
+                    self.length = 0;
+                }
+            }
+        }
+
+        if let Some(stats) = &mut self.stats {
+            stats.steps += 1;
+            stats.odd_steps += self.collatz.is_odd_step(value) as usize;
+            if next.magnitude_cmp(&stats.highest_point) == Ordering::Greater {
+                stats.highest_point = next.clone();
+                stats.peak_step = stats.steps;
+            }
+            let below_start = next.magnitude_cmp(&stats.start) == Ordering::Less;
+            if stats.stopping_time.is_none() && below_start {
+                stats.stopping_time = Some(stats.steps);
+            }
+        }
+        Some(next)
+    }
+}
+
+impl<'a, T: Value> Iterator for StreamOrbit<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let value = self.next.take()?;
+        // Step ahead, so that the orbit ends before a value repeats
+        self.next = self.step(&value);
+        Some(value)
+    }
+}
@@ -0,0 +1,151 @@
+use std::cmp::Ordering;
+use std::convert::{TryFrom, TryInto};
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+
+/// Integer type that orbit values are stored as.
+///
+/// `u64` is the default and is fast, but orbits that climb past `u64::MAX` need the
+/// arbitrary-precision backend, enabled with the `bignum` feature. Signed types extend the
+/// maps to the negative integers.
+pub trait Value: Clone + Eq + Ord + Hash + Debug + Display {
+    fn from_u64(n: u64) -> Self;
+
+    /// Remainder of `self` modulo `m`, which is never negative.
+    fn rem_u64(&self, m: u64) -> u64;
+
+    /// `self / d`, rounded down.
+    fn div_u64(&self, d: u64) -> Self;
+
+    /// `self * mul + add`, or `None` if it overflows.
+    fn mul_add(&self, mul: u64, add: u64) -> Option<Self>;
+
+    /// `self - rhs`, or `None` if it overflows.
+    fn sub_u64(&self, rhs: u64) -> Option<Self>;
+
+    /// Orders values by absolute value, which picks the root of a cycle.
+    fn magnitude_cmp(&self, other: &Self) -> Ordering {
+        self.cmp(other)
+    }
+
+    /// Lossy conversion used for plotting.
+    fn to_f64(&self) -> f64;
+}
+
+impl Value for u64 {
+    fn from_u64(n: u64) -> Self {
+        n
+    }
+
+    fn rem_u64(&self, m: u64) -> u64 {
+        self % m
+    }
+
+    fn div_u64(&self, d: u64) -> Self {
+        self / d
+    }
+
+    fn mul_add(&self, mul: u64, add: u64) -> Option<Self> {
+        self.checked_mul(mul)?.checked_add(add)
+    }
+
+    fn sub_u64(&self, rhs: u64) -> Option<Self> {
+        self.checked_sub(rhs)
+    }
+
+    fn to_f64(&self) -> f64 {
+        *self as f64
+    }
+}
+
+impl Value for i64 {
+    fn from_u64(n: u64) -> Self {
+        i64::try_from(n).expect("value out of range for i64")
+    }
+
+    fn rem_u64(&self, m: u64) -> u64 {
+        (*self as i128).rem_euclid(m as i128) as u64
+    }
+
+    fn div_u64(&self, d: u64) -> Self {
+        (*self as i128).div_euclid(d as i128) as i64
+    }
+
+    fn mul_add(&self, mul: u64, add: u64) -> Option<Self> {
+        self.checked_mul(mul.try_into().ok()?)?
+            .checked_add(add.try_into().ok()?)
+    }
+
+    fn sub_u64(&self, rhs: u64) -> Option<Self> {
+        self.checked_sub(rhs.try_into().ok()?)
+    }
+
+    fn magnitude_cmp(&self, other: &Self) -> Ordering {
+        self.unsigned_abs().cmp(&other.unsigned_abs())
+    }
+
+    fn to_f64(&self) -> f64 {
+        *self as f64
+    }
+}
+
+#[cfg(feature = "bignum")]
+impl Value for num_bigint::BigUint {
+    fn from_u64(n: u64) -> Self {
+        n.into()
+    }
+
+    fn rem_u64(&self, m: u64) -> u64 {
+        use num_traits::ToPrimitive;
+        (self % m).to_u64().unwrap()
+    }
+
+    fn div_u64(&self, d: u64) -> Self {
+        self / d
+    }
+
+    fn mul_add(&self, mul: u64, add: u64) -> Option<Self> {
+        Some(self * mul + add)
+    }
+
+    fn sub_u64(&self, rhs: u64) -> Option<Self> {
+        num_traits::CheckedSub::checked_sub(self, &rhs.into())
+    }
+
+    fn to_f64(&self) -> f64 {
+        num_traits::ToPrimitive::to_f64(self).unwrap_or(f64::INFINITY)
+    }
+}
+
+#[cfg(feature = "bignum")]
+impl Value for num_bigint::BigInt {
+    fn from_u64(n: u64) -> Self {
+        n.into()
+    }
+
+    fn rem_u64(&self, m: u64) -> u64 {
+        use num_integer::Integer;
+        use num_traits::ToPrimitive;
+        self.mod_floor(&m.into()).to_u64().unwrap()
+    }
+
+    fn div_u64(&self, d: u64) -> Self {
+        num_integer::Integer::div_floor(self, &d.into())
+    }
+
+    fn mul_add(&self, mul: u64, add: u64) -> Option<Self> {
+        Some(self * mul + add)
+    }
+
+    fn sub_u64(&self, rhs: u64) -> Option<Self> {
+        Some(self - rhs)
+    }
+
+    fn magnitude_cmp(&self, other: &Self) -> Ordering {
+        self.magnitude().cmp(other.magnitude())
+    }
+
+    fn to_f64(&self) -> f64 {
+        num_traits::ToPrimitive::to_f64(self).unwrap_or(f64::NAN)
+    }
+}
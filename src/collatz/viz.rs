@@ -1,24 +1,153 @@
+use std::convert::TryFrom;
+
+use plotters::style::{Color, HSLColor, Palette, Palette99, RGBAColor};
 use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
 
-use super::Collatz;
+use super::{AffineMap, Collatz, CollatzError, CollatzKind, DepthTable};
+use crate::utils::set_panic_hook;
+use crate::DrawResult;
 
 pub mod common_ancestor_dist;
 pub mod fraction_above;
+pub mod layer_growth;
 pub mod orbit_length;
+pub mod orbit_stats;
+pub mod points;
+pub mod predecessors;
+pub mod progress;
+
+pub use points::{Plot, PlotRequest, PointBuffer};
+pub use progress::Progress;
+
+use progress::Filling;
 
 #[wasm_bindgen]
 pub struct CollatzViz {
-    data: [Option<Collatz>; 4],
+    data: [Option<Collatz<i64>>; 5],
+    depth_tables: [Option<DepthTable>; 5],
+    map: AffineMap,
+    memory_budget: Option<usize>,
+    dense: bool,
+    filling: Option<Filling>,
+}
+
+/// Where the orbits of two values meet, see [`CollatzViz::merge`].
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct MergePoint {
+    pub value: f64,
+    /// Steps from the first value to `value`
+    pub steps_a: u32,
+    /// Steps from the second value to `value`
+    pub steps_b: u32,
+    /// Distance between the two values in the tree
+    pub distance: u32,
+}
+
+impl Default for CollatzViz {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[wasm_bindgen]
 impl CollatzViz {
     pub fn new() -> Self {
+        set_panic_hook();
         Self {
-            data: [None, None, None, None],
+            data: [None, None, None, None, None],
+            depth_tables: [None, None, None, None, None],
+            map: AffineMap::default(),
+            memory_budget: None,
+            dense: false,
+            filling: None,
+        }
+    }
+
+    /// Sets the map used by `CollatzKind::Affine`, dropping its tree if the map changed.
+    pub fn set_affine_map(
+        &mut self,
+        multiplier: u32,
+        addend: u32,
+        divisor: u32,
+        shortcut: bool,
+    ) -> Result<(), JsValue> {
+        let map = AffineMap::new(multiplier.into(), addend.into(), divisor.into(), shortcut)
+            .map_err(|err| err.to_string())?;
+        if map != self.map {
+            self.map = map;
+            self.data[CollatzKind::Affine as usize] = None;
+            self.drop_filling(CollatzKind::Affine);
+        }
+        Ok(())
+    }
+
+    /// Limits every tree to `bytes`, or lifts the limit with `None`.
+    pub fn set_memory_budget(&mut self, bytes: Option<usize>) {
+        self.memory_budget = bytes;
+        for collatz in self.data.iter_mut().flatten() {
+            collatz.set_memory_budget(bytes);
+        }
+    }
+
+    /// Plots orbit lengths of ranges starting at 1 from a [`DepthTable`] instead of the
+    /// tree, which takes far less memory for large ranges.
+    pub fn set_dense(&mut self, dense: bool) {
+        self.dense = dense;
+    }
+
+    /// Approximate number of bytes taken up by all trees and depth tables.
+    pub fn memory_usage(&self) -> usize {
+        self.data.iter().flatten().map(Collatz::memory_usage).sum::<usize>()
+            + self
+                .depth_tables
+                .iter()
+                .flatten()
+                .map(DepthTable::memory_usage)
+                .sum::<usize>()
+    }
+
+    /// Drops the tree and depth table of `kind`, returning the number of bytes freed.
+    pub fn reset(&mut self, kind: i32) -> usize {
+        let kind = CollatzKind::from(kind);
+        self.drop_filling(kind);
+        let kind = kind as usize;
+        self.data[kind]
+            .take()
+            .map_or(0, |collatz| collatz.memory_usage())
+            + self.depth_tables[kind]
+                .take()
+                .map_or(0, |table| table.memory_usage())
+    }
+
+    /// Shrinks the tree of `kind` to the orbits of `min..=max`, returning the number of
+    /// bytes freed.
+    pub fn prune(&mut self, kind: i32, min: i32, max: i32) -> usize {
+        let kind = CollatzKind::from(kind);
+        self.drop_filling(kind);
+        match &mut self.data[kind as usize] {
+            Some(collatz) => collatz.prune(min.into()..=max.into()),
+            None => 0,
         }
     }
 
+    /// Where the orbits of `a` and `b` meet in the tree of `kind`, or `undefined` if they end
+    /// in different cycles.
+    pub fn merge(&mut self, kind: i32, a: f64, b: f64) -> Result<Option<MergePoint>, JsValue> {
+        let (a, b) = (safe_integer(a)?, safe_integer(b)?);
+        let merge = self
+            .collatz(CollatzKind::from(kind))
+            .merge(a, b)
+            .map_err(|err| err.to_string())?;
+        Ok(merge.map(|merge| MergePoint {
+            value: merge.value as f64,
+            steps_a: merge.steps[0] as u32,
+            steps_b: merge.steps[1] as u32,
+            distance: merge.distance as u32,
+        }))
+    }
+
     pub fn get_length_string(&self) -> String {
         let lens = self
             .data
@@ -34,3 +163,147 @@ impl CollatzViz {
         lens.join(" ")
     }
 }
+
+impl CollatzViz {
+    /// Drops the fill in progress if it fills the tree of `kind`, which is going away.
+    fn drop_filling(&mut self, kind: CollatzKind) {
+        if self.filling.as_ref().is_some_and(|filling| filling.kind == kind) {
+            self.filling = None;
+        }
+    }
+
+    /// The tree of `kind`, created on first use.
+    fn collatz(&mut self, kind: CollatzKind) -> &mut Collatz<i64> {
+        let map = self.map;
+        let memory_budget = self.memory_budget;
+        self.data[kind as usize].get_or_insert_with(|| {
+            let mut collatz = match kind {
+                CollatzKind::Affine => Collatz::with_map(map),
+                kind => Collatz::with_kind(kind),
+            };
+            collatz.set_memory_budget(memory_budget);
+            collatz
+        })
+    }
+
+    /// The tree of `kind`, containing the orbits of every start value in `min..=max`, without
+    /// filling in anything else below `min`.
+    fn fill(
+        &mut self,
+        kind: CollatzKind,
+        min: i64,
+        max: i64,
+    ) -> Result<&Collatz<i64>, CollatzError<i64>> {
+        let collatz = self.collatz(kind);
+        // Everything but 0, which is a cycle of its own for some kinds
+        if max >= 1 {
+            collatz.generate_fill(min.max(1)..max + 1)?;
+        }
+        if min <= -1 {
+            collatz.generate_fill(min..max.min(-1) + 1)?;
+        }
+        Ok(collatz)
+    }
+
+    /// The orbits of every start value in `min..=max`, from the depth table of `kind` when
+    /// that is enabled and the range starts at 1, or else from its tree.
+    fn orbits(&mut self, kind: CollatzKind, min: i64, max: i64) -> DrawResult<&dyn Orbits> {
+        if !self.dense || min != 1 || kind == CollatzKind::Affine {
+            return Ok(self.fill(kind, min, max)?);
+        }
+        if max < min || max < 1 {
+            return Err(format!("the range {}..={} is empty", min, max).into());
+        }
+        let max = max as u64;
+        let table = &mut self.depth_tables[kind as usize];
+        if table.as_ref().is_none_or(|table| table.max() < max) {
+            if let Some(budget) = self.memory_budget {
+                if DepthTable::dense_size(kind, max) > budget {
+                    return Err(CollatzError::<u64>::MemoryBudget { budget }.into());
+                }
+            }
+            *table = Some(DepthTable::new(kind, max)?);
+        }
+        Ok(table.as_ref().unwrap())
+    }
+}
+
+/// What the plots of whole orbits read, from a tree or a depth table.
+trait Orbits {
+    fn in_domain(&self, n: i64) -> bool;
+
+    fn depth(&self, n: i64) -> Option<usize>;
+
+    /// Values of the orbit of `n`, excluding the root.
+    fn orbit(&self, n: i64) -> Option<Box<dyn Iterator<Item = i64> + '_>>;
+
+    /// Color of the `i`th of `len` points, started from `n`.
+    fn color(&self, n: i64, i: usize, len: usize) -> RGBAColor;
+}
+
+impl Orbits for Collatz<i64> {
+    fn in_domain(&self, n: i64) -> bool {
+        self.in_domain(&n)
+    }
+
+    fn depth(&self, n: i64) -> Option<usize> {
+        self.get_depth(n)
+    }
+
+    fn orbit(&self, n: i64) -> Option<Box<dyn Iterator<Item = i64> + '_>> {
+        Some(Box::new(self.iter_orbit(n)?.map(|data| data.value)))
+    }
+
+    fn color(&self, n: i64, i: usize, len: usize) -> RGBAColor {
+        point_color(self, n, i, len)
+    }
+}
+
+impl Orbits for DepthTable {
+    fn in_domain(&self, n: i64) -> bool {
+        n >= 1 && self.in_domain(n as u64)
+    }
+
+    fn depth(&self, n: i64) -> Option<usize> {
+        self.get_depth(u64::try_from(n).ok()?)
+    }
+
+    fn orbit(&self, n: i64) -> Option<Box<dyn Iterator<Item = i64> + '_>> {
+        let orbit = self.iter_orbit(u64::try_from(n).ok()?)?;
+        Some(Box::new(orbit.map(|value| value as i64)))
+    }
+
+    fn color(&self, _n: i64, i: usize, len: usize) -> RGBAColor {
+        gradient_color(i, len)
+    }
+}
+
+/// `n` as an integer, if it is one that a JS number represents exactly.
+fn safe_integer(n: f64) -> Result<i64, String> {
+    // Number.MAX_SAFE_INTEGER
+    const MAX: f64 = ((1u64 << 53) - 1) as f64;
+    if n.fract() == 0. && n.abs() <= MAX {
+        Ok(n as i64)
+    } else {
+        Err(format!("{} is not a safe integer", n))
+    }
+}
+
+/// The start values in `min..=max` the map of `orbits` is defined on, except 0.
+fn starts(orbits: &dyn Orbits, min: i64, max: i64) -> impl Iterator<Item = i64> + '_ {
+    (min..=max).filter(move |&n| n != 0 && orbits.in_domain(n))
+}
+
+/// Colors the `i`th of `len` points, started from `n`, by the cycle it reaches. With a
+/// single cycle, the points are colored along a gradient instead.
+fn point_color(collatz: &Collatz<i64>, n: i64, i: usize, len: usize) -> RGBAColor {
+    if collatz.cycles().len() > 1 {
+        Palette99::pick(collatz.cycle_of(&n).unwrap_or_default()).to_rgba()
+    } else {
+        gradient_color(i, len)
+    }
+}
+
+fn gradient_color(i: usize, len: usize) -> RGBAColor {
+    HSLColor(i as f64 / len as f64, 1., 0.5).to_rgba()
+}
@@ -0,0 +1,90 @@
+use std::convert::TryFrom;
+
+use crate::collatz::CollatzKind;
+use crate::{Chart, DrawResult};
+use plotters::prelude::*;
+use plotters_canvas::CanvasBackend;
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
+
+use super::CollatzViz;
+
+const KINDS: [CollatzKind; 5] = [
+    CollatzKind::Full,
+    CollatzKind::Short,
+    CollatzKind::Odd,
+    CollatzKind::Compact,
+    CollatzKind::Affine,
+];
+
+/// Deepest layer plotted, about where the layers of `Full` stop fitting `i64`. The layers of
+/// the other kinds stop earlier, once they get too wide or too far from zero for `i64`.
+pub const MAX_LAYER_DEPTH: usize = 64;
+
+#[wasm_bindgen]
+impl CollatzViz {
+    /// Plots the base 10 logarithm of how many values are at each depth of the inverse tree
+    /// from 1, for every kind, against `(4/3)^depth`. `max_depth` is at most
+    /// [`MAX_LAYER_DEPTH`], and the layers of a kind stop early once they get too wide; see
+    /// [`crate::collatz::Collatz::inverse_layer_counts`].
+    pub fn layer_growth(&mut self, canvas_id: &str, max_depth: i32) -> Result<Chart, JsValue> {
+        let max_depth = usize::try_from(max_depth).map_err(|err| err.to_string())?;
+        Ok(self
+            .draw_layer_growth(canvas_id, max_depth)
+            .map_err(|err| err.to_string())?)
+    }
+}
+
+impl CollatzViz {
+    pub fn draw_layer_growth(&mut self, canvas_id: &str, max_depth: usize) -> DrawResult<Chart> {
+        if max_depth > MAX_LAYER_DEPTH {
+            return Err(format!("the depth can be at most {}", MAX_LAYER_DEPTH).into());
+        }
+        // Counted from the rules, so the trees do not need to grow
+        let layers = KINDS
+            .iter()
+            .map(|&kind| self.collatz(kind).inverse_layer_counts(1, max_depth))
+            .collect::<Vec<_>>();
+        let max_count = layers.iter().flatten().copied().max().unwrap_or(1);
+
+        let backend = CanvasBackend::new(canvas_id).expect("cannot find canvas");
+        let root = backend.into_drawing_area();
+
+        root.fill(&WHITE)?;
+
+        let mut chart = ChartBuilder::on(&root)
+            .margin(20u32)
+            .build_cartesian_2d(
+                0f64..max_depth.max(1) as f64,
+                0f64..(max_count as f64).log10().max(1.),
+            )?;
+
+        for (&kind, counts) in KINDS.iter().zip(&layers) {
+            let color = Palette99::pick(kind as usize);
+            let points = counts
+                .iter()
+                .enumerate()
+                .map(|(depth, &count)| (depth as f64, (count as f64).log10()));
+            chart
+                .draw_series(LineSeries::new(points, &color))?
+                .label(format!("{:?}", kind))
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], &color));
+        }
+        let growth = (4. / 3f64).log10();
+        let expected = (0..=max_depth).map(|depth| (depth as f64, depth as f64 * growth));
+        chart
+            .draw_series(LineSeries::new(expected, &BLACK))?
+            .label("(4/3)^depth")
+            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], BLACK));
+
+        chart
+            .configure_series_labels()
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()?;
+
+        root.present()?;
+
+        Ok(Chart {})
+    }
+}
@@ -0,0 +1,100 @@
+use crate::collatz::{CollatzKind, NodeData};
+use crate::{Chart, DrawResult};
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
+
+use super::{point_color, starts, CollatzViz, PointBuffer};
+
+#[wasm_bindgen]
+impl CollatzViz {
+    /// Plots the stopping time, or glide, of every start value that drops below itself.
+    pub fn stopping_time(
+        &mut self,
+        canvas_id: &str,
+        kind: i32,
+        min: i32,
+        max: i32,
+    ) -> Result<Chart, JsValue> {
+        let kind = CollatzKind::from(kind);
+        Ok(self
+            .draw_orbit_stat(canvas_id, kind, min.into(), max.into(), |data| {
+                data.stopping_time
+            })
+            .map_err(|err| err.to_string())?)
+    }
+
+    pub fn odd_steps(
+        &mut self,
+        canvas_id: &str,
+        kind: i32,
+        min: i32,
+        max: i32,
+    ) -> Result<Chart, JsValue> {
+        let kind = CollatzKind::from(kind);
+        Ok(self
+            .draw_orbit_stat(canvas_id, kind, min.into(), max.into(), |data| {
+                Some(data.odd_steps)
+            })
+            .map_err(|err| err.to_string())?)
+    }
+
+    pub fn peak_step(
+        &mut self,
+        canvas_id: &str,
+        kind: i32,
+        min: i32,
+        max: i32,
+    ) -> Result<Chart, JsValue> {
+        let kind = CollatzKind::from(kind);
+        Ok(self
+            .draw_orbit_stat(canvas_id, kind, min.into(), max.into(), |data| {
+                Some(data.peak_step)
+            })
+            .map_err(|err| err.to_string())?)
+    }
+}
+
+impl CollatzViz {
+    /// Plots `stat` of every start value in `min..=max`, skipping those it is `None` for.
+    pub fn draw_orbit_stat(
+        &mut self,
+        canvas_id: &str,
+        kind: CollatzKind,
+        min: i64,
+        max: i64,
+        stat: fn(&NodeData<i64>) -> Option<usize>,
+    ) -> DrawResult<Chart> {
+        self.orbit_stat_points(kind, min, max, stat)?
+            .draw(canvas_id)
+    }
+
+    pub fn orbit_stat_points(
+        &mut self,
+        kind: CollatzKind,
+        min: i64,
+        max: i64,
+        stat: fn(&NodeData<i64>) -> Option<usize>,
+    ) -> DrawResult<PointBuffer> {
+        let collatz = self.fill(kind, min, max)?;
+
+        let mut points = Vec::new();
+        let mut max_height = 0.;
+        for n in starts(collatz, min, max) {
+            let y = match collatz.get_data(&n).and_then(stat) {
+                Some(y) => y as f64,
+                None => continue,
+            };
+            let x = n as f64;
+            max_height = y.max(max_height);
+            points.push((n, (x, y)));
+        }
+
+        Ok(PointBuffer::colored(
+            min as f64..max as f64,
+            0f64..max_height,
+            1,
+            &points,
+            |n, i, len| point_color(collatz, n, i, len),
+        ))
+    }
+}
@@ -0,0 +1,267 @@
+//! Scatter plots computed apart from drawing them, so that the trees can be generated in a
+//! Web Worker while the main thread only draws.
+//!
+//! Messages between the two are arrays of `f64`, which `postMessage` copies as a
+//! `Float64Array`. The main thread sends a [`PlotRequest`] from
+//! [`CollatzViz::plot_request`], the worker answers with the [`PointBuffer`] from
+//! [`CollatzViz::compute`], and the main thread draws it with [`CollatzViz::render`].
+
+use std::convert::TryFrom;
+use std::ops::Range;
+
+use crate::collatz::CollatzKind;
+use crate::{Chart, DrawResult};
+use plotters::prelude::*;
+use plotters_canvas::CanvasBackend;
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
+
+use super::{safe_integer, CollatzViz};
+
+/// First value of a [`PlotRequest`] message.
+const REQUEST: f64 = 1.;
+/// First value of a [`PointBuffer`] message.
+const POINTS: f64 = 2.;
+
+/// The plots a [`PlotRequest`] can ask for, numbered as in the page.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Plot {
+    OrbitLength = 0,
+    FractionAbove = 1,
+    CommonAncestorDist = 2,
+    StoppingTime = 3,
+    OddSteps = 4,
+    PeakStep = 5,
+    PredecessorCount = 6,
+}
+
+impl TryFrom<f64> for Plot {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(plot: f64) -> Result<Self, Self::Error> {
+        const PLOTS: [Plot; 7] = [
+            Plot::OrbitLength,
+            Plot::FractionAbove,
+            Plot::CommonAncestorDist,
+            Plot::StoppingTime,
+            Plot::OddSteps,
+            Plot::PeakStep,
+            Plot::PredecessorCount,
+        ];
+        PLOTS
+            .iter()
+            .copied()
+            .find(|&p| p as u32 as f64 == plot)
+            .ok_or_else(|| format!("{} is not a plot", plot).into())
+    }
+}
+
+/// The points of a plot to compute.
+///
+/// As a message, this is `[REQUEST, plot, kind, min, max, bound]`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlotRequest {
+    pub plot: Plot,
+    pub kind: CollatzKind,
+    pub min: i64,
+    pub max: i64,
+    /// Largest predecessor counted by `Plot::PredecessorCount`
+    pub bound: i64,
+}
+
+impl PlotRequest {
+    pub fn to_message(&self) -> Vec<f64> {
+        vec![
+            REQUEST,
+            self.plot as u32 as f64,
+            self.kind as i32 as f64,
+            self.min as f64,
+            self.max as f64,
+            self.bound as f64,
+        ]
+    }
+
+    pub fn from_message(message: &[f64]) -> DrawResult<Self> {
+        match *message {
+            [first, plot, kind, min, max, bound] if first == REQUEST => Ok(Self {
+                plot: Plot::try_from(plot)?,
+                kind: CollatzKind::from(kind as i32),
+                min: safe_integer(min)?,
+                max: safe_integer(max)?,
+                bound: safe_integer(bound)?,
+            }),
+            _ => Err("not a plot request".into()),
+        }
+    }
+}
+
+/// The points of a scatter plot, each drawn as a circle of its own color.
+///
+/// As a message, this is `[POINTS, x_start, x_end, y_start, y_end, radius, x, y, rgba, ...]`
+/// with the color packed into 32 bits.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PointBuffer {
+    pub x_range: Range<f64>,
+    pub y_range: Range<f64>,
+    pub radius: u32,
+    pub points: Vec<(f64, f64)>,
+    pub colors: Vec<RGBAColor>,
+}
+
+impl PointBuffer {
+    /// Colors the `i`th of `points`, started from `n`, with `color(n, i, len)`.
+    pub(super) fn colored(
+        x_range: Range<f64>,
+        y_range: Range<f64>,
+        radius: u32,
+        points: &[(i64, (f64, f64))],
+        color: impl Fn(i64, usize, usize) -> RGBAColor,
+    ) -> Self {
+        Self {
+            x_range,
+            y_range,
+            radius,
+            points: points.iter().map(|&(_, p)| p).collect(),
+            colors: points
+                .iter()
+                .enumerate()
+                .map(|(i, &(n, _))| color(n, i, points.len()))
+                .collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn to_message(&self) -> Vec<f64> {
+        let mut message = vec![
+            POINTS,
+            self.x_range.start,
+            self.x_range.end,
+            self.y_range.start,
+            self.y_range.end,
+            self.radius.into(),
+        ];
+        message.reserve(3 * self.len());
+        for (&(x, y), &RGBAColor(r, g, b, a)) in self.points.iter().zip(&self.colors) {
+            let a = (a * 255.).round() as u8;
+            message.extend_from_slice(&[x, y, u32::from_be_bytes([r, g, b, a]).into()]);
+        }
+        message
+    }
+
+    pub fn from_message(message: &[f64]) -> DrawResult<Self> {
+        if message.len() < 6 || message[0] != POINTS {
+            return Err("not a point buffer".into());
+        }
+        let (header, points) = message[1..].split_at(5);
+        if points.len() % 3 != 0 {
+            return Err("point buffer is cut off".into());
+        }
+        let points = points.chunks_exact(3);
+        Ok(Self {
+            x_range: header[0]..header[1],
+            y_range: header[2]..header[3],
+            radius: header[4] as u32,
+            colors: points
+                .clone()
+                .map(|point| {
+                    let [r, g, b, a] = (point[2] as u32).to_be_bytes();
+                    RGBAColor(r, g, b, a as f64 / 255.)
+                })
+                .collect(),
+            points: points.map(|point| (point[0], point[1])).collect(),
+        })
+    }
+
+    pub fn draw(&self, canvas_id: &str) -> DrawResult<Chart> {
+        let backend = CanvasBackend::new(canvas_id).expect("cannot find canvas");
+        let root = backend.into_drawing_area();
+
+        root.fill(&WHITE)?;
+
+        let mut chart = ChartBuilder::on(&root)
+            .margin(20u32)
+            .build_cartesian_2d(self.x_range.clone(), self.y_range.clone())?;
+
+        chart.draw_series(
+            self.points
+                .iter()
+                .zip(&self.colors)
+                .map(|(&p, &color)| Circle::new(p, self.radius, color)),
+        )?;
+
+        root.present()?;
+
+        Ok(Chart {})
+    }
+}
+
+#[wasm_bindgen]
+impl CollatzViz {
+    /// The message asking a worker for the points of `plot`.
+    pub fn plot_request(
+        plot: Plot,
+        kind: i32,
+        min: f64,
+        max: f64,
+        bound: f64,
+    ) -> Result<Vec<f64>, JsValue> {
+        let request = PlotRequest {
+            plot,
+            kind: CollatzKind::from(kind),
+            min: safe_integer(min)?,
+            max: safe_integer(max)?,
+            bound: safe_integer(bound)?,
+        };
+        Ok(request.to_message())
+    }
+
+    /// Answers a [`PlotRequest`] message with a [`PointBuffer`] message, without drawing.
+    pub fn compute(&mut self, request: &[f64]) -> Result<Vec<f64>, JsValue> {
+        Ok(self
+            .compute_points(request)
+            .map_err(|err| err.to_string())?
+            .to_message())
+    }
+
+    /// Draws a [`PointBuffer`] message.
+    pub fn render(canvas_id: &str, points: &[f64]) -> Result<Chart, JsValue> {
+        Ok(PointBuffer::from_message(points)
+            .and_then(|points| points.draw(canvas_id))
+            .map_err(|err| err.to_string())?)
+    }
+}
+
+impl CollatzViz {
+    /// Same as [`CollatzViz::compute`], without encoding the points as a message.
+    pub fn compute_points(&mut self, request: &[f64]) -> DrawResult<PointBuffer> {
+        self.points(&PlotRequest::from_message(request)?)
+    }
+
+    /// The points of the plot `request` asks for.
+    pub fn points(&mut self, request: &PlotRequest) -> DrawResult<PointBuffer> {
+        let PlotRequest {
+            plot,
+            kind,
+            min,
+            max,
+            bound,
+        } = *request;
+        match plot {
+            Plot::OrbitLength => self.orbit_length_points(kind, min, max),
+            Plot::FractionAbove => self.fraction_above_points(kind, min, max),
+            Plot::CommonAncestorDist => self.common_ancestor_dist_points(kind, min, max),
+            Plot::StoppingTime => self.orbit_stat_points(kind, min, max, |data| data.stopping_time),
+            Plot::OddSteps => self.orbit_stat_points(kind, min, max, |data| Some(data.odd_steps)),
+            Plot::PeakStep => self.orbit_stat_points(kind, min, max, |data| Some(data.peak_step)),
+            Plot::PredecessorCount => self.predecessor_count_points(kind, min, max, bound),
+        }
+    }
+}
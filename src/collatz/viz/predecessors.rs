@@ -0,0 +1,69 @@
+use crate::collatz::CollatzKind;
+use crate::{Chart, DrawResult};
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
+
+use super::{point_color, starts, CollatzViz, PointBuffer};
+
+#[wasm_bindgen]
+impl CollatzViz {
+    /// Plots how many predecessors from 1 up to `bound` every start value has, or from
+    /// `-bound` up to -1 for negative start values.
+    pub fn predecessor_count(
+        &mut self,
+        canvas_id: &str,
+        kind: i32,
+        min: i32,
+        max: i32,
+        bound: i32,
+    ) -> Result<Chart, JsValue> {
+        let kind = CollatzKind::from(kind);
+        Ok(self
+            .draw_predecessor_count(canvas_id, kind, min.into(), max.into(), bound.into())
+            .map_err(|err| err.to_string())?)
+    }
+}
+
+impl CollatzViz {
+    pub fn draw_predecessor_count(
+        &mut self,
+        canvas_id: &str,
+        kind: CollatzKind,
+        min: i64,
+        max: i64,
+        bound: i64,
+    ) -> DrawResult<Chart> {
+        self.predecessor_count_points(kind, min, max, bound)?
+            .draw(canvas_id)
+    }
+
+    pub fn predecessor_count_points(
+        &mut self,
+        kind: CollatzKind,
+        min: i64,
+        max: i64,
+        bound: i64,
+    ) -> DrawResult<PointBuffer> {
+        let collatz = self.collatz(kind);
+        let starts = starts(collatz, min, max).collect::<Vec<_>>();
+
+        let mut points = Vec::new();
+        let mut max_height = 0.;
+        for n in starts {
+            // Negative start values count the predecessors from -bound up to -1
+            let bound = if n < 0 { -bound } else { bound };
+            let y = collatz.count_predecessors(n, &bound)? as f64;
+            max_height = y.max(max_height);
+            points.push((n, (n as f64, y)));
+        }
+        let collatz = &*collatz;
+
+        Ok(PointBuffer::colored(
+            min as f64..max as f64,
+            0f64..max_height,
+            1,
+            &points,
+            |n, i, len| point_color(collatz, n, i, len),
+        ))
+    }
+}
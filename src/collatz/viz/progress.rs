@@ -0,0 +1,108 @@
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::JsValue;
+
+use crate::collatz::{CollatzKind, FillProgress};
+
+use super::CollatzViz;
+
+/// How far the fill started with [`CollatzViz::start_fill`] has come.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    /// Fraction of the start values filled, from 0 to 1
+    pub fraction: f64,
+    /// Number of nodes added to the tree so far
+    pub nodes: u32,
+    /// Milliseconds since the fill started
+    pub elapsed_ms: f64,
+    pub done: bool,
+}
+
+/// A fill of the tree of `kind` that goes a few start values at a time.
+pub(super) struct Filling {
+    pub(super) kind: CollatzKind,
+    /// The positive and negative parts of the range, as [`CollatzViz::fill`] splits it
+    parts: Vec<FillProgress<i64>>,
+    started_ms: f64,
+}
+
+impl Filling {
+    fn progress(&self) -> Progress {
+        let size = |part: &FillProgress<i64>| (part.range().end - part.range().start) as f64;
+        let total = self.parts.iter().map(size).sum::<f64>();
+        let filled = self
+            .parts
+            .iter()
+            .map(|part| part.fraction() * size(part))
+            .sum::<f64>();
+        let done = self.parts.iter().all(FillProgress::is_done);
+        Progress {
+            fraction: if done { 1. } else { filled / total },
+            nodes: self.parts.iter().map(FillProgress::nodes).sum::<usize>() as u32,
+            elapsed_ms: now_ms() - self.started_ms,
+            done,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl CollatzViz {
+    /// Starts filling the tree of `kind` with the orbits of `min..=max`, to be advanced with
+    /// [`CollatzViz::step_fill`] so that the page stays responsive. Any fill in progress is
+    /// dropped.
+    pub fn start_fill(&mut self, kind: i32, min: i32, max: i32) -> Progress {
+        let (min, max) = (i64::from(min), i64::from(max));
+        let mut parts = Vec::new();
+        // Everything but 0, as in `fill`
+        if max >= 1 {
+            parts.push(FillProgress::new(min.max(1)..max + 1));
+        }
+        if min <= -1 {
+            parts.push(FillProgress::new(min..max.min(-1) + 1));
+        }
+        let filling = Filling {
+            kind: CollatzKind::from(kind),
+            parts,
+            started_ms: now_ms(),
+        };
+        let progress = filling.progress();
+        self.filling = Some(filling);
+        progress
+    }
+
+    /// Fills at most `k` more start values of the fill started with
+    /// [`CollatzViz::start_fill`], and tells how far it has come. An error drops the fill.
+    pub fn step_fill(&mut self, k: u32) -> Result<Progress, JsValue> {
+        let mut filling = self.filling.take().ok_or("no fill in progress")?;
+        let collatz = self.collatz(filling.kind);
+        if let Some(part) = filling.parts.iter_mut().find(|part| !part.is_done()) {
+            collatz
+                .step_fill(part, k as usize)
+                .map_err(|err| err.to_string())?;
+        }
+        let progress = filling.progress();
+        if !progress.done {
+            self.filling = Some(filling);
+        }
+        Ok(progress)
+    }
+
+    /// Drops the fill in progress, keeping the orbits added so far.
+    pub fn cancel_fill(&mut self) {
+        self.filling = None;
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn now_ms() -> f64 {
+    js_sys::Date::now()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn now_ms() -> f64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0., |time| time.as_secs_f64() * 1000.)
+}
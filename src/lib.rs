@@ -1,4 +1,4 @@
-mod collatz;
+pub mod collatz;
 mod utils;
 
 use wasm_bindgen::prelude::*;
@@ -23,7 +23,6 @@ static ALLOCATOR: LockedAllocator<FreeListAllocator> = LockedAllocator::new(Free
 
 pub type DrawResult<T> = Result<T, Box<dyn std::error::Error>>;
 
+/// A chart drawn on a canvas, returned so that the page can tell a plot was drawn.
 #[wasm_bindgen]
-pub struct Chart {
-    convert: Box<dyn Fn((i32, i32)) -> Option<(f64, f64)>>,
-}
+pub struct Chart {}
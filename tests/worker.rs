@@ -0,0 +1,102 @@
+//! The messages between the page and a Web Worker, which run natively as well as with
+//! `wasm-pack test --node`.
+
+use collatz_viz::collatz::viz::{CollatzViz, Plot, PlotRequest, PointBuffer};
+use collatz_viz::collatz::{Collatz, CollatzKind};
+
+fn request(plot: Plot, kind: CollatzKind, min: i64, max: i64) -> PlotRequest {
+    PlotRequest {
+        plot,
+        kind,
+        min,
+        max,
+        bound: 1000,
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test::wasm_bindgen_test)]
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+fn request_round_trip() {
+    let request = request(Plot::PredecessorCount, CollatzKind::Odd, -99, 99);
+    let message = CollatzViz::plot_request(Plot::PredecessorCount, 2, -99., 99., 1000.).unwrap();
+    assert_eq!(message, request.to_message());
+    assert_eq!(PlotRequest::from_message(&message).unwrap(), request);
+    assert!(PlotRequest::from_message(&message[1..]).is_err());
+}
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test::wasm_bindgen_test)]
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+fn points_round_trip() {
+    let mut worker = CollatzViz::new();
+    let request = request(Plot::OrbitLength, CollatzKind::Full, 1, 100);
+    let message = worker.compute(&request.to_message()).unwrap();
+    let points = PointBuffer::from_message(&message).unwrap();
+    assert_eq!(points, worker.points(&request).unwrap());
+    assert_eq!(points.len(), 100);
+    assert_eq!(points.points[26], (27., 111.));
+    assert_eq!(points.x_range, 1.0..100.);
+    assert!(PointBuffer::from_message(&message[..message.len() - 1]).is_err());
+}
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test::wasm_bindgen_test)]
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+fn every_plot() {
+    let mut worker = CollatzViz::new();
+    for plot in [
+        Plot::OrbitLength,
+        Plot::FractionAbove,
+        Plot::CommonAncestorDist,
+        Plot::StoppingTime,
+        Plot::OddSteps,
+        Plot::PeakStep,
+        Plot::PredecessorCount,
+    ] {
+        let request = request(plot, CollatzKind::Short, -50, 50);
+        let points = worker.compute_points(&request.to_message()).unwrap();
+        assert!(!points.is_empty(), "{:?}", plot);
+        assert_eq!(points.colors.len(), points.len());
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test::wasm_bindgen_test)]
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+fn stepped_fill() {
+    let mut worker = CollatzViz::new();
+    let progress = worker.start_fill(1, -1000, 2000);
+    assert_eq!(progress.fraction, 0.);
+    assert!(!progress.done);
+    let mut steps = 0;
+    let progress = loop {
+        let progress = worker.step_fill(500).unwrap();
+        steps += 1;
+        if progress.done {
+            break progress;
+        }
+    };
+    // 2000 positive and 1000 negative start values
+    assert_eq!(steps, 6);
+    assert_eq!(progress.fraction, 1.);
+    assert!(progress.elapsed_ms >= 0.);
+
+    // The plots find the tree filled already
+    let request = request(Plot::OrbitLength, CollatzKind::Short, -1000, 2000);
+    let len = worker.get_length_string();
+    let points = worker.points(&request).unwrap();
+    assert_eq!(worker.get_length_string(), len);
+    assert_eq!(points, CollatzViz::new().points(&request).unwrap());
+    let len = len.split(' ').nth(1).unwrap().parse::<usize>().unwrap();
+    let cycle = Collatz::<i64>::with_kind(CollatzKind::Short).len();
+    assert_eq!(progress.nodes as usize, len - cycle);
+}
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test::wasm_bindgen_test)]
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+fn dense_ranges() {
+    let mut worker = CollatzViz::new();
+    worker.set_dense(true);
+    assert!(worker.orbit_length_points(CollatzKind::Full, 1, -1).is_err());
+    assert!(worker.orbit_length_points(CollatzKind::Full, 1, 0).is_err());
+    assert!(worker.orbit_length_points(CollatzKind::Full, 1, i64::MAX).is_err());
+    let points = worker.orbit_length_points(CollatzKind::Full, 1, 100).unwrap();
+    assert_eq!(points.points[26], (27., 111.));
+}
@@ -1,11 +1,23 @@
 import { Chart, CollatzViz, CollatzKind } from "collatz-viz"
 
 const canvas = document.querySelector("#canvas");
+const input_min = document.querySelector("#min");
 const input_max = document.querySelector("#max");
+const input_memory_budget = document.querySelector("#memory_budget");
+const memory_msg = document.querySelector("#memory");
+const error_msg = document.querySelector("#error");
+const merge_msg = document.querySelector("#merge");
+const fill_bar = document.querySelector("#fill_progress");
+const fill_msg = document.querySelector("#fill");
+
+// Start values filled between frames, so the page stays responsive on large ranges
+const FILL_STEP = 20000;
 
 let chart = null;
 let viz = null;
 let init = false;
+// Bumped by every update, so that a fill still going for an older one stops
+let fill_id = 0;
 
 export function main() {
   viz = CollatzViz.new();
@@ -20,6 +32,38 @@ function setupUI() {
   document.querySelectorAll("select").forEach(elem => {
     elem.addEventListener("input", updatePlot);
   });
+  document.querySelector("#free_memory").addEventListener("click", freeMemory);
+}
+
+function freeMemory() {
+  const collatz_kind = Number(document.querySelector("#collatz_kind").value);
+  // Keep only what the current plot needs, and drop the other kinds entirely
+  let freed = viz.prune(collatz_kind, Number(input_min.value), Number(input_max.value));
+  for (let kind = 0; kind < 5; kind++) {
+    if (kind !== collatz_kind) freed += viz.reset(kind);
+  }
+  showMemory(`freed ${formatBytes(freed)}`);
+}
+
+function showMemory(note) {
+  memory_msg.textContent = `Memory: ${formatBytes(viz.memory_usage())}` + (note ? ` (${note})` : "");
+}
+
+function showMerge(collatz_kind) {
+  const a = Number(document.querySelector("#merge_a").value);
+  const b = Number(document.querySelector("#merge_b").value);
+  try {
+    const merge = viz.merge(collatz_kind, a, b);
+    merge_msg.textContent = merge
+      ? `meet at ${merge.value} after ${merge.steps_a} and ${merge.steps_b} steps (distance ${merge.distance})`
+      : "never meet";
+  } catch (err) {
+    merge_msg.textContent = err;
+  }
+}
+
+function formatBytes(bytes) {
+  return `${(bytes / 1e6).toFixed(1)} MB`;
 }
 
 function setupCanvas() {
@@ -44,21 +88,85 @@ function updatePlot() {
     case "1": kind = CollatzKind.Short; break;
     case "2": kind = CollatzKind.Odd; break;
     case "3": kind = CollatzKind.Compact; break;
+    case "4": kind = CollatzKind.Affine; break;
     default: kind = CollatzKind.Full;
   }
 
   console.log("kind", kind);
 
   const plot_type = document.querySelector("#plot_type").value;
+  const id = ++fill_id;
+
+  error_msg.textContent = "";
+  try {
+    const budget = Number(input_memory_budget.value);
+    viz.set_memory_budget(budget > 0 ? budget * 1e6 : undefined);
+    viz.set_dense(document.querySelector("#dense").checked);
+    if (kind === CollatzKind.Affine) {
+      viz.set_affine_map(
+        Number(document.querySelector("#multiplier").value),
+        Number(document.querySelector("#addend").value),
+        Number(document.querySelector("#divisor").value),
+        document.querySelector("#shortcut").checked,
+      );
+    }
+    // Layer growth needs no tree, and dense plots read a depth table instead
+    if (plot_type === '7' || document.querySelector("#dense").checked) {
+      viz.cancel_fill();
+      drawPlot(collatz_kind, plot_type);
+      return;
+    }
+    showFill(viz.start_fill(Number(collatz_kind), Number(input_min.value), Number(input_max.value)));
+  } catch (err) {
+    error_msg.textContent = err;
+    return;
+  }
+  stepFill(id, collatz_kind, plot_type);
+}
+
+function stepFill(id, collatz_kind, plot_type) {
+  if (id !== fill_id) return;
+  let progress;
+  try {
+    progress = viz.step_fill(FILL_STEP);
+  } catch (err) {
+    error_msg.textContent = err;
+    showMemory();
+    return;
+  }
+  showFill(progress);
+  if (progress.done) {
+    drawPlot(collatz_kind, plot_type);
+  } else {
+    setTimeout(() => stepFill(id, collatz_kind, plot_type), 0);
+  }
+}
+
+function showFill(progress) {
+  fill_bar.value = progress.fraction;
+  fill_msg.textContent = `${progress.nodes} nodes in ${Math.ceil(progress.elapsed_ms)} ms`;
+}
 
+function drawPlot(collatz_kind, plot_type) {
   const start = performance.now();
-  switch (plot_type) {
-    case '0': chart = viz.orbit_length("canvas", Number(collatz_kind), Number(input_max.value)); break;
-    case '1': chart = viz.fraction_above("canvas", Number(collatz_kind), Number(input_max.value)); break;
-    case '2': chart = viz.common_ancestor_dist("canvas", Number(collatz_kind), Number(input_max.value)); break;
-    default: chart = null;
+  try {
+    switch (plot_type) {
+      case '0': chart = viz.orbit_length("canvas", Number(collatz_kind), Number(input_min.value), Number(input_max.value)); break;
+      case '1': chart = viz.fraction_above("canvas", Number(collatz_kind), Number(input_min.value), Number(input_max.value)); break;
+      case '2': chart = viz.common_ancestor_dist("canvas", Number(collatz_kind), Number(input_min.value), Number(input_max.value)); break;
+      case '3': chart = viz.stopping_time("canvas", Number(collatz_kind), Number(input_min.value), Number(input_max.value)); break;
+      case '4': chart = viz.odd_steps("canvas", Number(collatz_kind), Number(input_min.value), Number(input_max.value)); break;
+      case '5': chart = viz.peak_step("canvas", Number(collatz_kind), Number(input_min.value), Number(input_max.value)); break;
+      case '6': chart = viz.predecessor_count("canvas", Number(collatz_kind), Number(input_min.value), Number(input_max.value), Number(document.querySelector("#bound").value)); break;
+      case '7': chart = viz.layer_growth("canvas", Number(document.querySelector("#depth").value)); break;
+      default: chart = null;
+    }
+  } catch (err) {
+    error_msg.textContent = err;
   }
   const end = performance.now();
+  showMerge(Number(collatz_kind));
+  showMemory();
 
   console.log(`Rendered in ${Math.ceil(end - start)}`);
 }